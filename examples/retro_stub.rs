@@ -0,0 +1,185 @@
+/*
+    A tiny libretro frontend for trying the core without RetroArch. It loads the
+    core with dlopen, runs a program for a number of frames, checks that a save
+    state loads back to the same frames and draws the last frame as text
+        cargo build
+        cargo run --example retro_stub -- target/debug/libchip8.so programs/<program-name> [frames]
+*/
+
+#[cfg(unix)]
+mod stub {
+    use chip8::libretro::*;
+    use std::ffi::{c_void, CStr, CString};
+    use std::os::raw::c_uint;
+    use std::sync::Mutex;
+
+    struct Frame {
+        pixels: Vec<u32>,
+        width: usize,
+        height: usize,
+    }
+
+    static FRAME: Mutex<Frame> = Mutex::new(Frame { pixels: Vec::new(), width: 0, height: 0 });
+    static AUDIO_FRAMES: Mutex<usize> = Mutex::new(0);
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            ENVIRONMENT_SET_PIXEL_FORMAT => return *(data as *const c_uint) == PIXEL_FORMAT_XRGB8888,
+            ENVIRONMENT_SET_VARIABLES => {
+                let mut variable = data as *const Variable;
+                while !(*variable).key.is_null() {
+                    println!("option {}: {}", CStr::from_ptr((*variable).key).to_string_lossy(),
+                        CStr::from_ptr((*variable).value).to_string_lossy());
+                    variable = variable.add(1);
+                }
+                return true;
+            },
+            ENVIRONMENT_GET_VARIABLE_UPDATE => {
+                *(data as *mut bool) = false;
+                return true;
+            },
+            _ => return false,      // Every option keeps its default
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        let (width, height) = (width as usize, height as usize);
+        let mut frame = FRAME.lock().unwrap();
+        frame.width = width;
+        frame.height = height;
+        frame.pixels.clear();
+        for y in 0..height {
+            let row = (data as *const u8).add(y * pitch) as *const u32;
+            frame.pixels.extend_from_slice(std::slice::from_raw_parts(row, width));
+        }
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        *AUDIO_FRAMES.lock().unwrap() += frames;
+        return frames;
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, _id: c_uint) -> i16 {
+        return 0;
+    }
+
+    struct Core {
+        library: *mut c_void,
+    }
+
+    impl Core {
+        fn open(path: &str) -> Result<Core, String> {
+            let name = CString::new(path).map_err(|e| e.to_string())?;
+            let library = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+            if library.is_null() {
+                let error = unsafe { CStr::from_ptr(libc::dlerror()) };
+                return Err(format!("Could not load {}: {}", path, error.to_string_lossy()));
+            }
+            return Ok(Core { library });
+        }
+
+        // Look up a retro_* function, T has to be its function pointer type
+        unsafe fn function<T: Copy>(self: &Self, name: &str) -> T {
+            let symbol = CString::new(name).unwrap();
+            let address = libc::dlsym(self.library, symbol.as_ptr());
+            assert!(!address.is_null(), "The core does not export {}", name);
+            return std::mem::transmute_copy(&address);
+        }
+    }
+
+    fn run_frames(run: unsafe extern "C" fn(), frames: u64) -> Vec<u32> {
+        for _ in 0..frames {
+            unsafe { run(); }
+        }
+        return FRAME.lock().unwrap().pixels.clone();
+    }
+
+    pub fn main() -> Result<(), String> {
+        let args: Vec<String> = std::env::args().collect();
+        if args.len() < 3 {
+            return Err(format!("Usage: {} <core> <program> [frames]", args[0]));
+        }
+        let program = std::fs::read(&args[2]).map_err(|e| format!("Could not read {}: {}", args[2], e))?;
+        let frames = args.get(3).map_or(Ok(120), |value| value.parse::<u64>()).map_err(|e| e.to_string())?;
+        let core = Core::open(&args[1])?;
+
+        unsafe {
+            let api_version: unsafe extern "C" fn() -> c_uint = core.function("retro_api_version");
+            if api_version() != API_VERSION {
+                return Err(String::from("The core uses a different libretro API version"));
+            }
+            let mut info = std::mem::zeroed::<SystemInfo>();
+            core.function::<unsafe extern "C" fn(*mut SystemInfo)>("retro_get_system_info")(&mut info);
+            println!("{} {}", CStr::from_ptr(info.library_name).to_string_lossy(), CStr::from_ptr(info.library_version).to_string_lossy());
+
+            core.function::<unsafe extern "C" fn(EnvironmentFn)>("retro_set_environment")(environment);
+            core.function::<unsafe extern "C" fn(VideoRefreshFn)>("retro_set_video_refresh")(video_refresh);
+            core.function::<unsafe extern "C" fn(AudioSampleBatchFn)>("retro_set_audio_sample_batch")(audio_sample_batch);
+            core.function::<unsafe extern "C" fn(InputPollFn)>("retro_set_input_poll")(input_poll);
+            core.function::<unsafe extern "C" fn(InputStateFn)>("retro_set_input_state")(input_state);
+            core.function::<unsafe extern "C" fn()>("retro_init")();
+
+            let game = GameInfo {
+                path: std::ptr::null(),
+                data: program.as_ptr() as *const c_void,
+                size: program.len(),
+                meta: std::ptr::null(),
+            };
+            if !core.function::<unsafe extern "C" fn(*const GameInfo) -> bool>("retro_load_game")(&game) {
+                return Err(String::from("The core did not load the program"));
+            }
+            let mut av_info = std::mem::zeroed::<SystemAvInfo>();
+            core.function::<unsafe extern "C" fn(*mut SystemAvInfo)>("retro_get_system_av_info")(&mut av_info);
+            println!("{}x{} at {} fps, {} hz audio", av_info.geometry.base_width, av_info.geometry.base_height,
+                av_info.timing.fps, av_info.timing.sample_rate);
+
+            let run: unsafe extern "C" fn() = core.function("retro_run");
+            run_frames(run, frames);
+
+            // Save, run on, load and run the same frames again, they should match
+            let size = core.function::<unsafe extern "C" fn() -> usize>("retro_serialize_size")();
+            let mut state = vec![0u8; size];
+            if !core.function::<unsafe extern "C" fn(*mut c_void, usize) -> bool>("retro_serialize")(state.as_mut_ptr() as *mut c_void, size) {
+                return Err(String::from("retro_serialize failed"));
+            }
+            let first = run_frames(run, 60);
+            if !core.function::<unsafe extern "C" fn(*const c_void, usize) -> bool>("retro_unserialize")(state.as_ptr() as *const c_void, size) {
+                return Err(String::from("retro_unserialize failed"));
+            }
+            let second = run_frames(run, 60);
+            if first != second {
+                return Err(format!("Running on from the save state of {} bytes did not match", size));
+            }
+            println!("save state of {} bytes matches", size);
+            println!("{} audio frames", *AUDIO_FRAMES.lock().unwrap());
+
+            core.function::<unsafe extern "C" fn()>("retro_unload_game")();
+            core.function::<unsafe extern "C" fn()>("retro_deinit")();
+        }
+
+        let frame = FRAME.lock().unwrap();
+        let background = frame.pixels.first().copied().unwrap_or(0);
+        for y in 0..frame.height {
+            let row: String = (0..frame.width)
+                .map(|x| if frame.pixels[y * frame.width + x] != background { '#' } else { '.' })
+                .collect();
+            println!("{}", row);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(unix)]
+fn main() {
+    if let Err(e) = stub::main() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("The stub frontend uses dlopen, so it only runs on Linux and macOS");
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+const START: u32 = 0x200;
+
+/*
+    Assembles the classic CHIP-8 mnemonics (Cowgod's, the ones disassembler.rs
+    writes) into a program loaded at 0x200. One instruction per line, labels end
+    with a colon and can be used anywhere an address or number goes (but can not
+    be register or operand names like V0, I or DT), ; starts a comment. Numbers are decimal, #hex, 0xhex, $hex or %binary. DB and DW put
+    bytes and words in as they are, for sprites:
+
+        start:
+            LD I, sprite
+            LD V0, 10
+            DRW V0, V0, 5
+        loop:
+            JP loop
+        sprite:
+            DB #F0, #90, #F0, #90, #F0
+*/
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    // First find where every label is, then assemble with all of them known
+    let mut labels = HashMap::new();
+    let mut address = START;
+    for (number, line) in source.lines().enumerate() {
+        let (label, statement) = split_label(line).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        if let Some(label) = label {
+            if labels.insert(label.to_ascii_lowercase(), address).is_some() {
+                return Err(format!("Line {}: label '{}' is already used", number + 1, label));
+            }
+        }
+        if let Some(statement) = statement {
+            address += size(&statement).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+    }
+
+    let mut program = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        if let (_, Some(statement)) = split_label(line)? {
+            let bytes = encode(&statement, &labels).map_err(|e| format!("Line {}: {}: {}", number + 1, e, line.trim()))?;
+            program.extend(bytes);
+        }
+    }
+    if program.len() > 4096 - START as usize {
+        return Err(format!("The program is {} bytes, at most {} fit in memory", program.len(), 4096 - START));
+    }
+    return Ok(program);
+}
+
+struct Statement {
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+// The label on a line if there is one, and the instruction after it
+fn split_label(line: &str) -> Result<(Option<String>, Option<Statement>), String> {
+    let mut line = line.split(';').next().unwrap_or("").trim();
+    let mut label = None;
+    if let Some(colon) = line.find(':') {
+        let name = line[..colon].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("'{}' is not a label, they are letters, digits and _", name));
+        }
+        if is_reserved(name) {
+            return Err(format!("'{}' can not be a label, it is a register or operand name", name));
+        }
+        label = Some(name.to_string());
+        line = line[colon + 1..].trim();
+    }
+    if line.is_empty() {
+        return Ok((label, None));
+    }
+    let (mnemonic, rest) = match line.find(char::is_whitespace) {
+        Some(space) => (&line[..space], line[space..].trim()),
+        None => (line, ""),
+    };
+    let operands = if rest.is_empty() { Vec::new() } else { rest.split(',').map(|operand| operand.trim().to_string()).collect() };
+    return Ok((label, Some(Statement { mnemonic: mnemonic.to_ascii_uppercase(), operands })));
+}
+
+// Operand names, a label called any of these could never be used as one
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    return match upper.as_str() {
+        "B" | "F" | "K" | "DT" | "ST" | "I" => true,
+        _ => upper.len() == 2 && upper.starts_with('V') && u8::from_str_radix(&upper[1..], 16).is_ok(),
+    };
+}
+
+// How many bytes a statement takes, the labels are not needed for that
+fn size(statement: &Statement) -> Result<u32, String> {
+    return match statement.mnemonic.as_str() {
+        "DB" => Ok(statement.operands.len() as u32),
+        "DW" => Ok(statement.operands.len() as u32 * 2),
+        _ => Ok(2),
+    };
+}
+
+#[derive(PartialEq)]
+enum Operand {
+    Register(u16),          // V0 to VF
+    Number(u32),            // Numbers and labels
+    I,
+    IndirectI,              // [I]
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+}
+
+fn operand(text: &str, labels: &HashMap<String, u32>) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        _ if upper.len() == 2 && upper.starts_with('V') && u16::from_str_radix(&upper[1..], 16).is_ok() => {
+            Operand::Register(u16::from_str_radix(&upper[1..], 16).unwrap())
+        },
+        _ => Operand::Number(number(text, labels)?),
+    };
+    return Ok(operand);
+}
+
+fn number(text: &str, labels: &HashMap<String, u32>) -> Result<u32, String> {
+    let parsed = if let Some(hex) = text.strip_prefix('#').or_else(|| text.strip_prefix('$')) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        u32::from_str_radix(binary, 2)
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse::<u32>()
+    } else {
+        return labels.get(&text.to_ascii_lowercase()).copied().ok_or_else(|| format!("there is no label '{}'", text));
+    };
+    return parsed.map_err(|_| format!("'{}' is not a number", text));
+}
+
+fn encode(statement: &Statement, labels: &HashMap<String, u32>) -> Result<Vec<u8>, String> {
+    let operands = statement.operands.iter().map(|text| operand(text, labels)).collect::<Result<Vec<Operand>, String>>()?;
+    match statement.mnemonic.as_str() {
+        "DB" => return operands.iter().map(|operand| Ok(limit(operand_number(operand)?, 0xFF)? as u8)).collect(),
+        "DW" => {
+            let mut bytes = Vec::new();
+            for operand in &operands {
+                let word = limit(operand_number(operand)?, 0xFFFF)?;
+                bytes.extend_from_slice(&(word as u16).to_be_bytes());
+            }
+            return Ok(bytes);
+        },
+        _ => {},
+    }
+
+    use Operand::*;
+    let address = |value: u32| limit(value, 0xFFF).map(|value| value as u16);
+    let byte = |value: u32| limit(value, 0xFF).map(|value| value as u16);
+    let opcode: u16 = match (statement.mnemonic.as_str(), operands.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Number(n)]) => address(*n)?,
+        ("JP", [Number(n)]) => 0x1000 | address(*n)?,
+        ("JP", [Register(0), Number(n)]) => 0xB000 | address(*n)?,
+        ("CALL", [Number(n)]) => 0x2000 | address(*n)?,
+        ("SE", [Register(x), Number(n)]) => 0x3000 | x << 8 | byte(*n)?,
+        ("SNE", [Register(x), Number(n)]) => 0x4000 | x << 8 | byte(*n)?,
+        ("SE", [Register(x), Register(y)]) => 0x5000 | x << 8 | y << 4,
+        ("LD", [Register(x), Number(n)]) => 0x6000 | x << 8 | byte(*n)?,
+        ("ADD", [Register(x), Number(n)]) => 0x7000 | x << 8 | byte(*n)?,
+        ("LD", [Register(x), Register(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [Register(x), Register(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [Register(x), Register(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [Register(x), Register(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [Register(x), Register(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [Register(x), Register(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [Register(x)]) => 0x8006 | x << 8 | x << 4,      // Shifts VX whatever the shift quirk is
+        ("SHR", [Register(x), Register(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", [Register(x), Register(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [Register(x)]) => 0x800E | x << 8 | x << 4,
+        ("SHL", [Register(x), Register(y)]) => 0x800E | x << 8 | y << 4,
+        ("SNE", [Register(x), Register(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [I, Number(n)]) => 0xA000 | address(*n)?,
+        ("RND", [Register(x), Number(n)]) => 0xC000 | x << 8 | byte(*n)?,
+        ("DRW", [Register(x), Register(y), Number(n)]) => 0xD000 | x << 8 | y << 4 | limit(*n, 0xF)? as u16,
+        ("SKP", [Register(x)]) => 0xE09E | x << 8,
+        ("SKNP", [Register(x)]) => 0xE0A1 | x << 8,
+        ("AUDIO", []) => 0xF002,
+        ("LD", [Register(x), DelayTimer]) => 0xF007 | x << 8,
+        ("LD", [Register(x), Key]) => 0xF00A | x << 8,
+        ("LD", [DelayTimer, Register(x)]) => 0xF015 | x << 8,
+        ("LD", [SoundTimer, Register(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, Register(x)]) => 0xF01E | x << 8,
+        ("LD", [Font, Register(x)]) => 0xF029 | x << 8,
+        ("LD", [Bcd, Register(x)]) => 0xF033 | x << 8,
+        ("PITCH", [Register(x)]) => 0xF03A | x << 8,
+        ("LD", [IndirectI, Register(x)]) => 0xF055 | x << 8,
+        ("LD", [Register(x), IndirectI]) => 0xF065 | x << 8,
+        _ => return Err(String::from("not an instruction")),
+    };
+    return Ok(opcode.to_be_bytes().to_vec());
+}
+
+fn operand_number(operand: &Operand) -> Result<u32, String> {
+    return match operand {
+        Operand::Number(n) => Ok(*n),
+        _ => Err(String::from("DB and DW only take numbers and labels")),
+    };
+}
+
+fn limit(value: u32, max: u32) -> Result<u32, String> {
+    if value > max {
+        return Err(format!("{} does not fit, the most it can be is {}", value, max));
+    }
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
+
+    #[test]
+    fn every_opcode_assembles_back_from_its_disassembly() {
+        for opcode in 0..=0xFFFFu16 {
+            let program = opcode.to_be_bytes();
+            let listing = disassembler::disassemble(&program);
+            assert_eq!(assemble(&listing), Ok(program.to_vec()), "{}", listing);
+        }
+    }
+
+    #[test]
+    fn register_and_operand_names_are_not_labels() {
+        for name in ["b", "F", "k", "DT", "st", "I", "v0", "VA", "vf"] {
+            let error = assemble(&format!("{}: CLS", name)).unwrap_err();
+            assert!(error.contains("can not be a label"), "{}", error);
+        }
+        assert_eq!(assemble("vg: JP vg\nbcd: JP bcd"), Ok(vec![0x12, 0x00, 0x12, 0x02]));
+    }
+}
@@ -0,0 +1,455 @@
+use super::wav;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/*
+    The beeper. A Synth turns "the sound timer is running" into samples: the tone
+    fades in and out over a few milliseconds so it does not click, and every beep
+    lasts at least minimum_beep so one or two frame beeps are still heard. The
+    same Synth is used by the SDL callback, recordings and the libretro core, so
+    they all sound the same and recordings come out identical every time. Once an
+    XO-CHIP game loads an audio pattern that is played instead of the waveform
+*/
+
+pub const RECORDING_SAMPLE_RATE: u32 = 44100;
+
+pub const WAVEFORMS: [&str; 3] = ["square", "triangle", "sine"];
+
+const ATTACK_SECONDS: f32 = 0.002;
+const RELEASE_SECONDS: f32 = 0.005;
+
+#[derive(Clone)]
+pub enum Waveform {
+    Square,                 // Band limited so it does not alias at high pitches
+    Triangle,
+    Sine,
+    Sample(Vec<f32>),       // One cycle of a wave from a WAV file, played at the pitch
+}
+
+impl Waveform {
+    // One of WAVEFORMS or a WAV file holding one cycle of the wave
+    pub fn parse(value: &str) -> Result<Waveform, String> {
+        return match value {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            _ if value.to_ascii_lowercase().ends_with(".wav") => {
+                let (samples, _) = wav::read(value)?;
+                if samples.is_empty() {
+                    return Err(format!("{} has no samples", value));
+                }
+                Ok(Waveform::Sample(samples))
+            },
+            _ => Err(format!("Waveform '{}' must be one of {} or a .wav file", value, WAVEFORMS.join(", "))),
+        };
+    }
+
+    // The wave at a point in its cycle, dt is how far the cycle moves each sample
+    fn value(self: &Self, phase: f32, dt: f32) -> f32 {
+        return match self {
+            Waveform::Square => {
+                let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+                naive + poly_blep(phase, dt) - poly_blep((phase + 0.5) % 1.0, dt)
+            },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Sample(samples) => {
+                let position = phase * samples.len() as f32;
+                let index = position as usize % samples.len();
+                let next = samples[(index + 1) % samples.len()];
+                samples[index] + (next - samples[index]) * position.fract()
+            },
+        };
+    }
+}
+
+// Smooths the square wave's jumps over a sample on each side, which removes most of the aliasing
+fn poly_blep(phase: f32, dt: f32) -> f32 {
+    if phase < dt {
+        let t = phase / dt;
+        return t + t - t * t - 1.0;
+    }
+    if phase > 1.0 - dt {
+        let t = (phase - 1.0) / dt;
+        return t * t + t + t + 1.0;
+    }
+    return 0.0;
+}
+
+// What the beeper should play, taken from the Cpu every frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub beeping: bool,
+    pub pattern: Option<[u8; 16]>,  // XO-CHIP 1 bit audio, 128 bits played in a loop
+    pub pitch: u8,
+}
+
+// XO-CHIP's pattern playback rate in bits per second
+pub fn pattern_rate(pitch: u8) -> f32 {
+    return 4000.0 * 2f32.powf((f32::from(pitch) - 64.0) / 48.0);
+}
+
+#[derive(Clone)]
+pub struct BeeperSettings {
+    pub waveform: Waveform,
+    pub pitch: f32,             // Hz
+    pub volume: f32,            // 0 to 1
+    pub minimum_beep: f32,      // Seconds, 0 plays beeps exactly as long as the sound timer runs
+}
+
+impl Default for BeeperSettings {
+    fn default() -> BeeperSettings {
+        return BeeperSettings {
+            waveform: Waveform::Square,
+            pitch: 330.0,
+            volume: 0.1,
+            minimum_beep: 0.035,
+        };
+    }
+}
+
+/*
+    The beeper state the emulation writes and the audio thread reads. The thread
+    only wakes up every few milliseconds, so besides whether the beeper is on it
+    counts the beeps, which lets it notice a beep that started and stopped in
+    between and still play it for the minimum length
+*/
+#[derive(Default)]
+pub struct BeeperState {
+    gate: AtomicBool,
+    beeps: AtomicU32,
+    has_pattern: AtomicBool,
+    pattern: [AtomicU64; 2],
+    pitch: AtomicU8,
+}
+
+impl BeeperState {
+    pub fn set(self: &Self, tone: &Tone) {
+        // The pattern goes first so a beep never starts with the old one
+        let pattern = tone.pattern.unwrap_or([0; 16]);
+        for (half, word) in self.pattern.iter().enumerate() {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&pattern[half * 8..half * 8 + 8]);
+            word.store(u64::from_be_bytes(bytes), Ordering::Release);
+        }
+        self.pitch.store(tone.pitch, Ordering::Release);
+        self.has_pattern.store(tone.pattern.is_some(), Ordering::Release);
+        if !self.gate.swap(tone.beeping, Ordering::AcqRel) && tone.beeping {
+            self.beeps.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    pub fn tone(self: &Self) -> Tone {
+        let mut pattern = [0; 16];
+        for (half, word) in self.pattern.iter().enumerate() {
+            pattern[half * 8..half * 8 + 8].copy_from_slice(&word.load(Ordering::Acquire).to_be_bytes());
+        }
+        return Tone {
+            beeping: self.gate.load(Ordering::Acquire),
+            pattern: if self.has_pattern.load(Ordering::Acquire) { Some(pattern) } else { None },
+            pitch: self.pitch.load(Ordering::Acquire),
+        };
+    }
+}
+
+/*
+    A queue of samples from the audio thread to the run loop, for capturing what
+    the device plays. It is made once at its full size, so the audio callback never
+    allocates or waits on a lock; one side only pushes and the other only pops.
+    Samples that do not fit because the reader fell behind are dropped and counted
+*/
+pub struct SampleRing {
+    samples: Box<[AtomicU32]>,  // The bits of the f32 samples
+    written: AtomicUsize,       // How many were pushed ever, the next one goes at this modulo the length
+    read: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> SampleRing {
+        return SampleRing {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        };
+    }
+
+    // From the audio thread
+    pub fn push(self: &Self, samples: &[f32]) {
+        let written = self.written.load(Ordering::Relaxed);
+        let free = self.samples.len() - written.wrapping_sub(self.read.load(Ordering::Acquire));
+        let count = samples.len().min(free);
+        for (i, sample) in samples[..count].iter().enumerate() {
+            self.samples[written.wrapping_add(i) % self.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written.wrapping_add(count), Ordering::Release);
+        if count < samples.len() {
+            self.dropped.fetch_add(samples.len() - count, Ordering::Relaxed);
+        }
+    }
+
+    // Add everything pushed since the last time to the end of out
+    pub fn pop(self: &Self, out: &mut Vec<f32>) {
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read);
+        out.extend((0..count).map(|i| f32::from_bits(self.samples[read.wrapping_add(i) % self.samples.len()].load(Ordering::Relaxed))));
+        self.read.store(written, Ordering::Release);
+    }
+
+    pub fn dropped(self: &Self) -> usize {
+        return self.dropped.load(Ordering::Relaxed);
+    }
+}
+
+pub struct Synth {
+    settings: BeeperSettings,
+    sample_rate: f32,
+    phase: f32,
+    envelope: f32,
+    gate: bool,
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    position: f32,              // Where we are in the pattern in bits, kept when the pattern changes
+    hold: u32,                  // Samples left of the minimum beep length
+    beeps: u32,                 // Beeps seen from the shared state so far
+}
+
+impl Synth {
+    pub fn new(settings: BeeperSettings, sample_rate: u32) -> Synth {
+        return Synth {
+            settings,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            envelope: 0.0,
+            gate: false,
+            pattern: None,
+            pitch: 64,
+            position: 0.0,
+            hold: 0,
+            beeps: 0,
+        };
+    }
+
+    pub fn set_tone(self: &mut Self, tone: &Tone) {
+        if tone.beeping && !self.gate {
+            self.hold = (self.settings.minimum_beep * self.sample_rate) as u32;
+        }
+        self.gate = tone.beeping;
+        self.pattern = tone.pattern;
+        self.pitch = tone.pitch;
+    }
+
+    pub fn fill(self: &mut Self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            *x = self.next_sample();
+        }
+    }
+
+    // Fill a buffer following the state shared with the emulation, for audio callbacks.
+    // The buffer is a few milliseconds, less than a frame, so the tone is read once
+    pub fn follow(self: &mut Self, state: &BeeperState, out: &mut [f32]) {
+        let tone = state.tone();
+        let beeps = state.beeps.load(Ordering::Acquire);
+        if beeps != self.beeps {
+            self.beeps = beeps;
+            self.gate = false;              // Start the beep even if it is already over
+            self.set_tone(&Tone { beeping: true, ..tone });
+        }
+        self.set_tone(&tone);
+        self.fill(out);
+    }
+
+    fn next_sample(self: &mut Self) -> f32 {
+        let on = self.gate || self.hold > 0;
+        self.hold = self.hold.saturating_sub(1);
+        if on {
+            self.envelope = (self.envelope + 1.0 / (ATTACK_SECONDS * self.sample_rate)).min(1.0);
+        } else {
+            self.envelope = (self.envelope - 1.0 / (RELEASE_SECONDS * self.sample_rate)).max(0.0);
+        }
+        if self.envelope == 0.0 {
+            self.phase = 0.0;               // Every beep starts at the same point in the wave
+            self.position = 0.0;
+            return 0.0;
+        }
+        let value = match self.pattern {
+            Some(pattern) => self.pattern_value(&pattern),
+            None => {
+                let dt = (self.settings.pitch / self.sample_rate).min(0.5);
+                let value = self.settings.waveform.value(self.phase, dt);
+                self.phase = (self.phase + dt) % 1.0;
+                value
+            },
+        };
+        return value * self.envelope * self.settings.volume;
+    }
+
+    // The average of the pattern's bits over the stretch this sample covers, which
+    // resamples the pattern's rate to ours without the aliasing of just picking a bit
+    fn pattern_value(self: &mut Self, pattern: &[u8; 16]) -> f32 {
+        let step = pattern_rate(self.pitch) / self.sample_rate;   // Bits per sample
+        let mut remaining = step;
+        let mut total = 0.0;
+        while remaining > 0.0 {
+            let bit = self.position as usize % 128;
+            let length = (self.position.floor() + 1.0 - self.position).min(remaining);
+            let level = if (pattern[bit / 8] >> (7 - bit % 8)) & 0x01 == 1 { 1.0 } else { -1.0 };
+            total += level * length;
+            remaining -= length;
+            self.position += length;
+            if self.position >= 128.0 {
+                self.position -= 128.0;
+            }
+        }
+        return total / step;
+    }
+}
+
+// Generates the beeper one 60hz frame at a time, so recordings get the sound
+// without needing an audio device
+pub struct FrameAudio {
+    synth: Synth,
+    samples_per_frame: usize,
+}
+
+impl FrameAudio {
+    pub fn new(settings: &BeeperSettings) -> FrameAudio {
+        return FrameAudio {
+            synth: Synth::new(settings.clone(), RECORDING_SAMPLE_RATE),
+            samples_per_frame: (RECORDING_SAMPLE_RATE / 60) as usize,
+        };
+    }
+
+    pub fn next_frame(self: &mut Self, tone: &Tone) -> Vec<f32> {
+        let mut samples = vec![0.0; self.samples_per_frame];
+        self.synth.set_tone(tone);
+        self.synth.fill(&mut samples);
+        return samples;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synth(sample_rate: u32, minimum_beep: f32) -> Synth {
+        let settings = BeeperSettings { volume: 1.0, minimum_beep, ..BeeperSettings::default() };
+        return Synth::new(settings, sample_rate);
+    }
+
+    fn render(synth: &mut Synth, count: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; count];
+        synth.fill(&mut samples);
+        return samples;
+    }
+
+    fn sign_changes(samples: &[f32]) -> usize {
+        return samples.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+    }
+
+    // Alternating bits change sign once a bit, so a second of them is the rate
+    #[test]
+    fn patterns_play_at_the_xo_chip_rate() {
+        for (pitch, rate) in [(16, 2000), (64, 4000), (112, 8000)] {
+            assert_eq!(pattern_rate(pitch), rate as f32);
+            let mut synth = synth(44100, 0.0);
+            synth.set_tone(&Tone { beeping: true, pattern: Some([0xAA; 16]), pitch });
+            let changes = sign_changes(&render(&mut synth, 44100)) as i32;
+            assert!((changes - rate).abs() <= 2, "pitch {} changed sign {} times", pitch, changes);
+        }
+    }
+
+    // Changing the pattern or the pitch carries on from the same bit
+    #[test]
+    fn pattern_and_pitch_changes_keep_the_position() {
+        let mut synth = synth(8000, 0.0);
+        synth.set_tone(&Tone { beeping: true, pattern: Some([0xF0; 16]), pitch: 64 });
+        render(&mut synth, 100);
+        assert_eq!(synth.position, 50.0);
+        synth.set_tone(&Tone { beeping: true, pattern: Some([0x0F; 16]), pitch: 64 });
+        assert_eq!(render(&mut synth, 1)[0], -1.0);         // Bit 50 is set in 0xF0 and not in 0x0F
+        assert_eq!(synth.position, 50.5);
+        synth.set_tone(&Tone { beeping: true, pattern: Some([0x0F; 16]), pitch: 112 });
+        render(&mut synth, 3);
+        assert_eq!(synth.position, 53.5);
+    }
+
+    #[test]
+    fn beeps_fade_in_and_out() {
+        let mut synth = synth(10000, 0.0);
+        synth.settings.waveform = Waveform::Sine;
+        synth.settings.pitch = 2500.0;                      // Peaks every 4 samples, at 1, 5, 9...
+        synth.set_tone(&Tone { beeping: true, pattern: None, pitch: 64 });
+        let attack = render(&mut synth, 40);
+        let peaks: Vec<f32> = attack.iter().skip(1).step_by(4).copied().collect();
+        assert!(peaks.windows(2).take(4).all(|pair| pair[1] > pair[0]));
+        assert!(peaks[0] < 0.2);
+        assert!((peaks[9] - 1.0).abs() < 0.01);             // Full volume after 2 ms
+
+        synth.set_tone(&Tone { beeping: false, pattern: None, pitch: 64 });
+        let release = render(&mut synth, 60);
+        let peaks: Vec<f32> = release.iter().skip(1).step_by(4).copied().collect();
+        assert!(peaks.windows(2).take(12).all(|pair| pair[1] < pair[0]));
+        assert!(peaks[0] > 0.9);
+        assert!(release[50..].iter().all(|sample| *sample == 0.0));   // Silent after 5 ms
+    }
+
+    #[test]
+    fn short_beeps_last_the_minimum() {
+        let mut synth = synth(1000, 0.035);
+        synth.set_tone(&Tone { beeping: true, pattern: None, pitch: 64 });
+        synth.set_tone(&Tone { beeping: false, pattern: None, pitch: 64 });
+        let mut envelope = Vec::new();
+        for _ in 0..50 {
+            synth.next_sample();
+            envelope.push(synth.envelope);
+        }
+        assert!(envelope[1..35].iter().all(|level| *level == 1.0));
+        assert!(envelope[35] < 1.0 && envelope[35] > 0.0);
+        assert!(envelope[40..].iter().all(|level| *level == 0.0));
+    }
+
+    // 441 Hz at 44100 is 100 samples a cycle, flat at 1 and -1 between the edges. The
+    // edges fall right on a sample, which the band limiting puts halfway instead of
+    // jumping straight from one side to the other
+    #[test]
+    fn square_is_band_limited() {
+        let mut synth = synth(44100, 0.0);
+        synth.settings.pitch = 441.0;
+        synth.set_tone(&Tone { beeping: true, pattern: None, pitch: 64 });
+        let samples = render(&mut synth, 500);
+        let cycle = &samples[200..300];
+        for (i, sample) in cycle.iter().enumerate() {
+            assert!((sample - samples[300 + i]).abs() < 0.01);
+            match i {
+                0 | 50 => assert!(sample.abs() < 0.01),
+                1..=49 => assert!((sample - 1.0).abs() < 1e-4),
+                _ => assert!((sample + 1.0).abs() < 1e-4),
+            }
+        }
+        assert!(cycle.iter().all(|sample| sample.abs() <= 1.0));
+        assert_eq!(sign_changes(&samples[100..]), 8);
+    }
+
+    #[test]
+    fn ring_keeps_samples_in_order_and_drops_overflow() {
+        let ring = SampleRing::new(4);
+        let mut out = Vec::new();
+        ring.push(&[1.0, 2.0, 3.0]);
+        ring.pop(&mut out);
+        assert_eq!(out, [1.0, 2.0, 3.0]);
+
+        // Wraps around the end, and what does not fit is counted
+        ring.push(&[4.0, 5.0, 6.0]);
+        ring.push(&[7.0, 8.0]);
+        out.clear();
+        ring.pop(&mut out);
+        assert_eq!(out, [4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(ring.dropped(), 1);
+        out.clear();
+        ring.pop(&mut out);
+        assert!(out.is_empty());
+    }
+}
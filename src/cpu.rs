@@ -1,7 +1,105 @@
 use super::opcode::Opcode;
 use std::fs;
-use rand::Rng;
-use std::time::Instant;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+
+pub const PLATFORMS: [&str; 5] = ["default", "vip", "modern", "schip", "xochip"];
+
+pub const QUIRKS: [&str; 6] = ["shift", "load_store", "jump", "vf_reset", "clip", "display_wait"];
+
+/*
+    Behaviours that differ between CHIP-8 interpreters, games written for one may
+    need them. The names are Octo's, and the default is what this interpreter has
+    always done, which is SUPER-CHIP's except for BNNN
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    pub shift: bool,                // 8XY6 and 8XYE shift VX in place instead of putting VY shifted in VX
+    pub load_store: bool,           // FX55 and FX65 leave I alone instead of moving it past the registers
+    pub jump: bool,                 // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vf_reset: bool,             // 8XY1, 8XY2 and 8XY3 clear VF
+    pub clip: bool,                 // Sprites are cut off at the edges of the display instead of wrapping around
+    pub display_wait: bool,         // DXYN waits for the next 60hz frame like the COSMAC VIP
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        return Quirks::platform("default").unwrap();
+    }
+}
+
+impl Quirks {
+    // The quirks of the machines games were written for, one of PLATFORMS
+    pub fn platform(name: &str) -> Result<Quirks, String> {
+        let none = Quirks { shift: false, load_store: false, jump: false, vf_reset: false, clip: false, display_wait: false };
+        return match name {
+            "default" => Ok(Quirks { shift: true, load_store: true, clip: true, ..none }),
+            "vip" => Ok(Quirks { vf_reset: true, clip: true, display_wait: true, ..none }),
+            "modern" => Ok(Quirks { clip: true, ..none }),
+            "schip" => Ok(Quirks { shift: true, load_store: true, jump: true, clip: true, ..none }),
+            "xochip" => Ok(none),
+            _ => Err(format!("Platform '{}' must be one of {}", name, PLATFORMS.join(", "))),
+        };
+    }
+
+    pub fn set(self: &mut Self, name: &str, on: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "load_store" => &mut self.load_store,
+            "jump" => &mut self.jump,
+            "vf_reset" => &mut self.vf_reset,
+            "clip" => &mut self.clip,
+            "display_wait" => &mut self.display_wait,
+            _ => return Err(format!("Quirk '{}' must be one of {}", name, QUIRKS.join(", "))),
+        };
+        *quirk = on;
+        return Ok(());
+    }
+
+    // Turn quirks on or off from a list like "shift,-clip", a - in front turns it off
+    pub fn apply(self: &mut Self, list: &str) -> Result<(), String> {
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name.strip_prefix('-') {
+                Some(name) => self.set(name, false)?,
+                None => self.set(name, true)?,
+            }
+        }
+        return Ok(());
+    }
+
+    // The quirks that are on, in the same form apply takes
+    pub fn describe(self: &Self) -> String {
+        let on = [self.shift, self.load_store, self.jump, self.vf_reset, self.clip, self.display_wait];
+        let names: Vec<&str> = QUIRKS.iter().zip(on.iter()).filter(|(_, on)| **on).map(|(name, _)| *name).collect();
+        return names.join(",");
+    }
+}
+
+/*
+    xorshift64*, a random number generator whose whole state is one number, so it
+    fits in save states and movies and runs that use CXNN can be repeated
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rng {
+    pub state: u64,
+}
+
+impl Rng {
+    // Any seed works, 0 (which xorshift can not leave) is swapped for another one
+    pub fn new(seed: u64) -> Rng {
+        return Rng { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } };
+    }
+
+    pub fn byte(self: &mut Self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        return (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+    }
+}
 
 pub struct Cpu {
     pub memory: [u8; 4096],
@@ -14,9 +112,19 @@ pub struct Cpu {
     pub sound_timer: u8,
     pub keyboard: u16,              // Each bit will represent a key (16 keys)
     pub waiting_for_key_flag: bool,
-    pub display: [u8; 64 * 32],      // Each byte represent a pixel (Supposed to be 1 bit = 1 pixel)       
+    pub display: [u8; 64 * 32],     // Each byte is a logical pixel (0 off, 1 on), the frontend picks the colors
     pub draw_flag: u8,              // Do we need to draw on this interation
-    pub last_time: Instant,         // Keep track of time for the timers
+    pub waiting_for_vblank: bool,   // Set by DXYN with the display_wait quirk, cleared every frame
+    pub quirks: Quirks,
+    pub audio_pattern: Option<[u8; 16]>,    // XO-CHIP 1 bit sample loaded by F002, the plain beeper until then
+    pub pitch: u8,                  // XO-CHIP playback rate for the pattern, 64 is 4000 bits a second
+    pub rng: Rng,                   // For CXNN, seed it to make runs repeatable
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        return Cpu::new();
+    }
 }
 
 impl Cpu {
@@ -34,10 +142,20 @@ impl Cpu {
             waiting_for_key_flag: false,
             display: [0; 64*32],
             draw_flag: 0,
-            last_time: Instant::now(),
+            waiting_for_vblank: false,
+            quirks: Quirks::default(),
+            audio_pattern: None,
+            pitch: 64,
+            rng: Rng::new(rand::random()),
         };
     }
 
+    // Width and height of the display in pixels, frontends should check this every
+    // frame instead of assuming 64x32 so they keep up with resolution changes
+    pub fn resolution(self: &Self) -> (usize, usize) {
+        return (DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    }
+
     pub fn load_sprites(self: &mut Cpu) {
         let sprites: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0,     // 0
@@ -62,12 +180,22 @@ impl Cpu {
         }
     }
 
-    pub fn load_program(self: &mut Cpu, file: &str) {
-        
-        let program_bytes = fs::read(file).unwrap();
-        for (i, byte) in (&program_bytes).into_iter().enumerate(){
-            self.memory[512 + i] = *byte;
+    pub fn load_program(self: &mut Cpu, file: &str) -> Result<(), String> {
+        let program_bytes = fs::read(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+        return self.load_bytes(&program_bytes).map_err(|e| format!("{}: {}", file, e));
+    }
+
+    pub fn seed(self: &mut Self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // Copy a program that is already in memory (e.g. handed over by a libretro frontend) to 0x200
+    pub fn load_bytes(self: &mut Cpu, program: &[u8]) -> Result<(), String> {
+        if program.len() > self.memory.len() - 512 {
+            return Err(format!("Program is {} bytes, at most {} fit in memory", program.len(), self.memory.len() - 512));
         }
+        self.memory[512..512 + program.len()].copy_from_slice(program);
+        return Ok(());
     }
 
     // For when FX0A was called we should end up in this function to take care if it
@@ -79,17 +207,60 @@ impl Cpu {
         self.waiting_for_key_flag = false;
     }
 
-    pub fn execute(self: &mut Self, opcode: &[u8]) {
+    // Set the bit for a key in the keyboard. If we were waiting for a key event due to
+    // Fx0A we need to finish off the instruction by placing the key in register[x]
+    pub fn press_key(self: &mut Self, key: u8) {
+        self.keyboard |= 1 << key;
+        if self.waiting_for_key_flag {
+            self.key_pressed(key);
+        }
+    }
 
-        let new_time = Instant::now();
-        let elasped_time = new_time.duration_since(self.last_time);
-        if  elasped_time.as_micros() >= 16600 {                      // 1/60hz is 16.6666ms
-            self.last_time = new_time;                               // decrement timers every 16.6ms
-            if self.delay_timer > 0 { self.delay_timer -= 1; }
-            if self.sound_timer > 0 { self.sound_timer -= 1; }
+    pub fn release_key(self: &mut Self, key: u8) {
+        self.keyboard &= !(1 << key);
+    }
+
+    // Replace the whole keyboard at once (e.g. from a movie), going through press_key
+    // for the newly pressed keys so Fx0A still sees them
+    pub fn set_keyboard(self: &mut Self, keyboard: u16) {
+        let pressed = keyboard & !self.keyboard;
+        self.keyboard = keyboard;
+        for key in 0..16 {
+            if (pressed >> key) & 0x01 == 1 {
+                self.press_key(key);
+            }
         }
+    }
 
-        let op = Opcode::new(&opcode);
+    // Called once per 60hz frame by the run loop rather than from execute so the
+    // timers no longer depend on how fast the host runs instructions
+    pub fn tick_timers(self: &mut Self) {
+        if self.delay_timer > 0 { self.delay_timer -= 1; }
+        if self.sound_timer > 0 { self.sound_timer -= 1; }
+    }
+
+    // Fetch the opcode at the program counter and execute it, returns false if we
+    // are stalled waiting for a key or the next frame
+    pub fn cycle(self: &mut Self) -> bool {
+        // Program counter is incremented here because if we are waiting for a keypress
+        // we dont want to incremement it prematurely or we will end eup skipping instructions
+        // In addition it must be before execute as certain instruction modify where the PC is and
+        // they modify it to where they want it rather than the instruction before what they want
+        if self.waiting_for_key_flag || self.waiting_for_vblank {
+            return false;
+        }
+        let oparray = [
+            self.memory[usize::from(self.program_counter)],
+            self.memory[usize::from(self.program_counter) + 1]
+        ];
+        self.program_counter += 2;
+        self.execute(&oparray);
+        return true;
+    }
+
+    pub fn execute(self: &mut Self, opcode: &[u8]) {
+
+        let op = Opcode::new(opcode);
         match op.digits[0] {                    // Match the instructions with what needs to be executed
             0x0 => {
                 match op.digits[1..=3] {
@@ -158,7 +329,7 @@ impl Cpu {
             0x7 => {
                 // 7XNN where we set registers[X] = registers[X] + NN, do not set carry flag
                 let x = usize::from(op.digits[1]);
-                let nn = ((op.digits[2] << 4) | op.digits[3]) as u16;
+                let nn = (op.digits[2] << 4) | op.digits[3];
                 let xnn = u16::from(self.registers[x]) + nn;            // Rust panics due to overflow
                 self.registers[x] = (xnn & 0x00FF) as u8;               // So we just truncate afterwards instead
             },
@@ -171,48 +342,55 @@ impl Cpu {
                     [x, y, 0x1] => {
                         // Set register[x] to register[x] OR register[y]
                         self.registers[usize::from(x)] |= self.registers[usize::from(y)];
+                        self.reset_flag();
                     },
                     [x, y, 0x2] => {
                         // Set register[x] to register[x] AND register[y]
                         self.registers[usize::from(x)] &= self.registers[usize::from(y)];
+                        self.reset_flag();
                     },
                     [x, y, 0x3] => {
                         // Set register[x] to register[x] XOR register[y]
                         self.registers[usize::from(x)] ^= self.registers[usize::from(y)];
+                        self.reset_flag();
                     },
                     [x, y, 0x4] => {
                         // Set register[x] to register[x] + register[y], set carry if needed
-                        let regx = u16::from(self.registers[usize::from(x)]);
-                        let regy = u16::from(self.registers[usize::from(y)]);
-                        let result = regx + regy;
-                        self.registers[0xF] = u8::from(result > 255);
-                        self.registers[usize::from(x)] = result as u8;
+                        // The flag is written last so it wins when x is 0xF
+                        let regx = self.registers[usize::from(x)];
+                        let regy = self.registers[usize::from(y)];
+                        let (result, carry) = regx.overflowing_add(regy);
+                        self.registers[usize::from(x)] = result;
+                        self.registers[0xF] = u8::from(carry);
                     },
                     [x, y, 0x5] => {
-                        // Set register[x] to register[x] - register[y], set flag if underflow
+                        // Set register[x] to register[x] - register[y], set flag if no borrow
                         let regx = self.registers[usize::from(x)];
                         let regy = self.registers[usize::from(y)];
-                        let (result, _) = regx.overflowing_sub(regy);   // May not be correct
-                        self.registers[0xF] = u8::from(regx > regy);    // 1 when no borrow  
-                        self.registers[usize::from(x)] = result as u8;
+                        let (result, borrow) = regx.overflowing_sub(regy);
+                        self.registers[usize::from(x)] = result;
+                        self.registers[0xF] = u8::from(!borrow);       // 1 when no borrow (regx >= regy)
                     },
-                    [x, _y, 0x6] => {
+                    [x, y, 0x6] => {
                         // If LSB of reg[X] is 1, then reg[F] = 1 otherwise 0, then divide reg[X] by 2
-                        self.registers[0xF] = self.registers[usize::from(x)] & 0x01;
-                        self.registers[usize::from(x)] = self.registers[usize::from(x)] >> 1;
+                        // (without the shift quirk it is reg[Y] that is shifted into reg[X])
+                        let regx = self.shift_source(x, y);
+                        self.registers[usize::from(x)] = regx >> 1;
+                        self.registers[0xF] = regx & 0x01;
                     },
                     [x, y, 0x7] => {
-                        // Set register[x] to register[y] - register[x], set flag if underflow
+                        // Set register[x] to register[y] - register[x], set flag if no borrow
                         let regx = self.registers[usize::from(x)];
                         let regy = self.registers[usize::from(y)];
-                        let (result, _) = regy.overflowing_sub(regx);   // May not be correct
-                        self.registers[0xF] = u8::from(regy > regx);    // 1 when no borrow
-                        self.registers[usize::from(x)] = result as u8;
+                        let (result, borrow) = regy.overflowing_sub(regx);
+                        self.registers[usize::from(x)] = result;
+                        self.registers[0xF] = u8::from(!borrow);       // 1 when no borrow (regy >= regx)
                     },
-                    [x, _y, 0xE] => {
+                    [x, y, 0xE] => {
                         // If MSB of reg[X] is 1, then reg[F] = 1 otherwise 0, then multiply reg[X] by 2
-                        self.registers[0xF] = (self.registers[usize::from(x)] >> 7) & 0x01;
-                        self.registers[usize::from(x)] = self.registers[usize::from(x)] << 1;
+                        let regx = self.shift_source(x, y);
+                        self.registers[usize::from(x)] = regx << 1;
+                        self.registers[0xF] = (regx >> 7) & 0x01;
                     },
                     _ => { }
                 }
@@ -231,48 +409,44 @@ impl Cpu {
                 self.address_register = (op.digits[1] << 8) | (op.digits[2] << 4) | op.digits[3];
             },
             0xB => {
-                // Opcode is BNNN, jump to NNN + reg[0]
-                let reg0 = u16::from(self.registers[0]);
-                self.program_counter = ((op.digits[1] << 8) | (op.digits[2] << 4) | op.digits[3]) + reg0;
+                // Opcode is BNNN, jump to NNN + reg[0], or with the jump quirk BXNN to XNN + reg[X]
+                let register = if self.quirks.jump { usize::from(op.digits[1]) } else { 0 };
+                let offset = u16::from(self.registers[register]);
+                self.program_counter = (((op.digits[1] << 8) | (op.digits[2] << 4) | op.digits[3]) + offset) & 0x0FFF;
             },
             0xC => {
                 // Opcode is CXKK, set reg[X] to random byte AND KK
                 let kk = ((op.digits[2] << 4) | op.digits[3]) as u8;
-                self.registers[usize::from(op.digits[1])] = kk & random_byte();
+                self.registers[usize::from(op.digits[1])] = kk & self.rng.byte();
             },
             0xD => {
                 // Opcode is DXYN, Display N-byte sprite starting at address_register
                 // Place the sprite starting from reg[X], reg[Y] and set reg[F]=1 if any bit is erased
                 let i = usize::from(self.address_register);
                 let n = usize::from(op.digits[3]);
-                let x = usize::from(self.registers[usize::from(op.digits[1])]);
-                let y = usize::from(self.registers[usize::from(op.digits[2])]);
-                
-                let mut bit;                                                // Pixel for sprite
+                // The starting point always wraps around, the rest of the sprite only without the clip quirk
+                let x = usize::from(self.registers[usize::from(op.digits[1])]) % DISPLAY_WIDTH;
+                let y = usize::from(self.registers[usize::from(op.digits[2])]) % DISPLAY_HEIGHT;
+
                 self.registers[15] = 0;                                     // Initially no pixels have been erased yet
-                let mut pos;                                                // For indexing the display array
                 for idy in 0..n {
-
-                    let byte: u8 = self.memory[i + idy];
+                    let byte: u8 = self.memory[(i + idy) % self.memory.len()];
                     for idx in 0..8 {
-
-                        bit = (byte >> (7 - idx)) & 0x01;
-                        pos = x + idx + (y + idy)*64;
-                        if pos > 2047 { 
+                        let bit = (byte >> (7 - idx)) & 0x01;               // Pixel for sprite
+                        let (px, py) = (x + idx, y + idy);
+                        if self.quirks.clip && (px >= DISPLAY_WIDTH || py >= DISPLAY_HEIGHT) {
                             continue;
                         }
-                        if (bit == 1) && (self.display[pos] == 0xFF) {
+                        let pos = (px % DISPLAY_WIDTH) + (py % DISPLAY_HEIGHT) * DISPLAY_WIDTH;
+                        if (bit == 1) && (self.display[pos] == 1) {
                             self.registers[15] = 1;
                         }
-                        if bit == 1 { 
-                            self.display[pos] = self.display[pos] ^ 0xFF;
-                        }
-                        else {
-                            self.display[pos] = self.display[pos] ^ 0x00;
-                        }
+                        self.display[pos] ^= bit;
                     }
                 }
                 self.draw_flag = 1;
+                // Nothing else runs this frame, so at most one sprite is drawn per frame
+                self.waiting_for_vblank = self.quirks.display_wait;
             },
             0xE => {
                 match op.digits[1..=3] {
@@ -295,6 +469,15 @@ impl Cpu {
             },
             0xF => {
                 match op.digits[1..=3] {
+                    [0x0, 0x0, 0x2] => {
+                        // XO-CHIP: load the 16 byte (128 bit) audio pattern at I
+                        let i = usize::from(self.address_register);
+                        let mut pattern = [0; 16];
+                        for (pos, byte) in pattern.iter_mut().enumerate() {
+                            *byte = self.memory[(i + pos) % self.memory.len()];
+                        }
+                        self.audio_pattern = Some(pattern);
+                    },
                     [x, 0x0, 0x7] => {
                         self.registers[usize::from(x)] = self.delay_timer;
                     },
@@ -316,12 +499,16 @@ impl Cpu {
                         // Each sprite is 5 bytes long and they begin at mem location 0x0000
                         self.address_register = u16::from(self.registers[usize::from(x)]) * 5;
                     },
+                    [x, 0x3, 0xA] => {
+                        // XO-CHIP: set the pattern's playback pitch to reg[x]
+                        self.pitch = self.registers[usize::from(x)];
+                    },
                     [x, 0x3, 0x3] => {
                         let i = usize::from(self.address_register);
                         let value = self.registers[usize::from(x)];
-                        self.memory[i] = (value / 100) as u8;                   // The hundreds digit of reg[x]
-                        self.memory[i + 1] = ((value % 100) / 10) as u8;        // The tens digit of reg[x]
-                        self.memory[i + 2] = (value % 10) as u8;                // The ones digit of reg[x]
+                        self.memory[i] = value / 100;                           // The hundreds digit of reg[x]
+                        self.memory[i + 1] = (value % 100) / 10;                // The tens digit of reg[x]
+                        self.memory[i + 2] = value % 10;                        // The ones digit of reg[x]
                     },
                     [x, 0x5, 0x5] => {
                         let i = usize::from(self.address_register);
@@ -329,13 +516,15 @@ impl Cpu {
                         for pos in 0..=xpos {
                             self.memory[i + pos] = self.registers[pos];
                         }
+                        self.advance_index(xpos);
                     },
                     [x, 0x6, 0x5] => {
                         let i = usize::from(self.address_register);
                         let xpos = usize::from(x);
                         for pos in 0..=xpos {
                             self.registers[pos] = self.memory[i + pos];
-                        } 
+                        }
+                        self.advance_index(xpos);
                     },
                     _ => { },
                 }
@@ -345,8 +534,111 @@ impl Cpu {
     }
 }
 
-fn random_byte() -> u8 {
-    let num = rand::thread_rng().gen_range(0..255);
-    return num;
+impl Cpu {
+    // The COSMAC VIP's logic instructions left VF in a mess, games that rely on it expect 0
+    fn reset_flag(self: &mut Self) {
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn shift_source(self: &Self, x: u16, y: u16) -> u8 {
+        return self.registers[usize::from(if self.quirks.shift { x } else { y })];
+    }
+
+    // Without the load_store quirk FX55 and FX65 leave I just past the last register
+    fn advance_index(self: &mut Self, x: usize) {
+        if !self.quirks.load_store {
+            self.address_register = self.address_register.wrapping_add(x as u16 + 1);
+        }
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run 8XYN with VX and VY (V2) set, and give back VX and VF. When X is F the
+    // value goes in VF, the flag has to win over the result
+    fn alu(quirks: Quirks, x: u8, n: u8, vx: u8, vy: u8) -> (u8, u8) {
+        let mut cpu = Cpu::new();
+        cpu.quirks = quirks;
+        cpu.registers[usize::from(x)] = vx;
+        cpu.registers[2] = vy;
+        cpu.execute(&[0x80 | x, 0x20 | n]);
+        return (cpu.registers[usize::from(x)], cpu.registers[0xF]);
+    }
+
+    fn with(list: &str) -> Quirks {
+        let mut quirks = Quirks::platform("xochip").unwrap();
+        quirks.apply(list).unwrap();
+        return quirks;
+    }
+
+    #[test]
+    fn logic_resets_vf_with_the_quirk() {
+        for (n, result) in [(0x1, 0x3F), (0x2, 0x0C), (0x3, 0x33)].iter() {
+            let mut cpu = Cpu::new();
+            cpu.quirks = with("");
+            cpu.registers[1] = 0x0F;
+            cpu.registers[2] = 0x3C;
+            cpu.registers[0xF] = 0x55;
+            cpu.execute(&[0x81, 0x20 | n]);
+            assert_eq!((cpu.registers[1], cpu.registers[0xF]), (*result, 0x55));
+            assert_eq!(alu(with("vf_reset"), 0x1, *n, 0x0F, 0x3C), (*result, 0));
+            assert_eq!(alu(with(""), 0xF, *n, 0x0F, 0x3C), (*result, *result));
+            assert_eq!(alu(with("vf_reset"), 0xF, *n, 0x0F, 0x3C).1, 0);
+        }
+    }
+
+    #[test]
+    fn add_sets_the_carry() {
+        assert_eq!(alu(with(""), 0x1, 0x4, 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(with(""), 0x1, 0x4, 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(with(""), 0xF, 0x4, 0x10, 0x20).1, 0);
+        assert_eq!(alu(with(""), 0xF, 0x4, 0xFF, 0x02).1, 1);
+    }
+
+    #[test]
+    fn subtract_sets_no_borrow() {
+        assert_eq!(alu(with(""), 0x1, 0x5, 0x05, 0x03), (0x02, 1));
+        assert_eq!(alu(with(""), 0x1, 0x5, 0x03, 0x05), (0xFE, 0));
+        assert_eq!(alu(with(""), 0x1, 0x5, 0x04, 0x04), (0x00, 1));
+        assert_eq!(alu(with(""), 0xF, 0x5, 0x05, 0x03).1, 1);
+        assert_eq!(alu(with(""), 0xF, 0x5, 0x03, 0x05).1, 0);
+        assert_eq!(alu(with(""), 0xF, 0x5, 0x04, 0x04).1, 1);
+    }
+
+    #[test]
+    fn subtract_reversed_sets_no_borrow() {
+        assert_eq!(alu(with(""), 0x1, 0x7, 0x03, 0x05), (0x02, 1));
+        assert_eq!(alu(with(""), 0x1, 0x7, 0x05, 0x03), (0xFE, 0));
+        assert_eq!(alu(with(""), 0x1, 0x7, 0x04, 0x04), (0x00, 1));
+        assert_eq!(alu(with(""), 0xF, 0x7, 0x03, 0x05).1, 1);
+        assert_eq!(alu(with(""), 0xF, 0x7, 0x05, 0x03).1, 0);
+        assert_eq!(alu(with(""), 0xF, 0x7, 0x04, 0x04).1, 1);
+    }
+
+    #[test]
+    fn shift_right_keeps_the_low_bit() {
+        assert_eq!(alu(with("shift"), 0x1, 0x6, 0x05, 0x00), (0x02, 1));
+        assert_eq!(alu(with("shift"), 0x1, 0x6, 0x04, 0xFF), (0x02, 0));
+        assert_eq!(alu(with(""), 0x1, 0x6, 0xFF, 0x04), (0x02, 0));
+        assert_eq!(alu(with(""), 0x1, 0x6, 0x00, 0x05), (0x02, 1));
+        assert_eq!(alu(with("shift"), 0xF, 0x6, 0x05, 0x00).1, 1);
+        assert_eq!(alu(with("shift"), 0xF, 0x6, 0x04, 0x00).1, 0);
+        assert_eq!(alu(with(""), 0xF, 0x6, 0x00, 0x05).1, 1);
+    }
+
+    #[test]
+    fn shift_left_keeps_the_high_bit() {
+        assert_eq!(alu(with("shift"), 0x1, 0xE, 0x81, 0x00), (0x02, 1));
+        assert_eq!(alu(with("shift"), 0x1, 0xE, 0x41, 0xFF), (0x82, 0));
+        assert_eq!(alu(with(""), 0x1, 0xE, 0xFF, 0x41), (0x82, 0));
+        assert_eq!(alu(with(""), 0x1, 0xE, 0x00, 0x81), (0x02, 1));
+        assert_eq!(alu(with("shift"), 0xF, 0xE, 0x81, 0x00).1, 1);
+        assert_eq!(alu(with("shift"), 0xF, 0xE, 0x41, 0x00).1, 0);
+        assert_eq!(alu(with(""), 0xF, 0xE, 0x00, 0x81).1, 1);
+    }
+}
@@ -0,0 +1,224 @@
+use super::cpu::Quirks;
+use super::keymap::Keymap;
+use super::settings::{self, Settings};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+/*
+    What is known about programs, looked up by the SHA-1 of their bytes. The file
+    is the community CHIP-8 database's programs.json (github.com/chip-8/chip-8-database),
+    a list of programs with the roms that belong to them, and only the parts used
+    here are read. The copy built in only has the programs that have been checked
+    against their roms (the database is MIT licensed), and a programs.json in the
+    config folder is used instead of it, so the full database can be dropped in there
+*/
+const BUNDLED: &str = include_str!("../database/programs.json");
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,                             // The ones it runs on, the best first
+    tickrate: Option<u32>,                              // Instructions per frame
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,   // Quirks that differ from the platform's
+    #[serde(default)]
+    keys: HashMap<String, u8>,                          // up, down, left, right, a and b to hex keys
+}
+
+// A program found in the database
+pub struct Entry {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<String>,   // One of cpu::PLATFORMS
+    pub quirks: Option<String>,     // On top of the platform, in --quirks form
+    pub ipf: Option<u32>,
+    pub keys: Vec<(String, u8)>,    // What the program uses the keys for and which keys those are
+}
+
+impl Entry {
+    // The database's settings, to go under the program's own ones
+    pub fn settings(self: &Self) -> Settings {
+        return Settings {
+            platform: self.platform.clone(),
+            quirks: self.quirks.clone(),
+            ipf: self.ipf,
+            ..Settings::default()
+        };
+    }
+
+    // The platform's quirks with the program's own differences on top
+    pub fn quirks(self: &Self) -> Result<Quirks, String> {
+        let mut quirks = Quirks::platform(self.platform.as_deref().unwrap_or("default"))?;
+        if let Some(list) = &self.quirks {
+            quirks.apply(list)?;
+        }
+        return Ok(quirks);
+    }
+
+    // Put the keys the program moves with on the arrow keys and the controller's
+    // d-pad and stick, and the buttons on Space, Left Shift, A and B
+    pub fn add_keys(self: &Self, keymap: &mut Keymap) {
+        for (name, key) in &self.keys {
+            let (host_keys, inputs): (&[&str], &[&str]) = match name.as_str() {
+                "up" => (&["Up"], &["dpup", "lefty-"]),
+                "down" => (&["Down"], &["dpdown", "lefty+"]),
+                "left" => (&["Left"], &["dpleft", "leftx-"]),
+                "right" => (&["Right"], &["dpright", "leftx+"]),
+                "a" => (&["Space"], &["a"]),
+                "b" => (&["Left Shift"], &["b"]),
+                _ => continue,
+            };
+            keymap.add(*key, host_keys, inputs);
+        }
+    }
+}
+
+// The program's entry, None when the database does not have it
+pub fn lookup(program: &[u8]) -> Result<Option<Entry>, String> {
+    let user_file = settings::folder().map(|folder| folder.join("programs.json")).filter(|path| path.exists());
+    return match &user_file {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            find(&text, program).map_err(|e| format!("{}: {}", path.display(), e))
+        },
+        None => find(BUNDLED, program).map_err(|e| format!("The built in database is broken: {}", e)),
+    };
+}
+
+// The program's entry in a database's text
+fn find(text: &str, program: &[u8]) -> Result<Option<Entry>, String> {
+    let programs: Vec<Program> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let hash = settings::sha1(program);
+    for entry in programs {
+        if let Some(rom) = entry.roms.get(&hash) {
+            let platform = rom.platforms.iter().find_map(|platform| platform_name(platform));
+            let quirks = rom.platforms.iter().find(|platform| platform_name(platform).is_some())
+                .and_then(|platform| rom.quirky_platforms.get(platform))
+                .map(quirk_list)
+                .filter(|list| !list.is_empty());
+            let mut keys: Vec<(String, u8)> = rom.keys.iter().filter(|(_, key)| **key < 16).map(|(name, key)| (name.clone(), *key)).collect();
+            keys.sort();
+            return Ok(Some(Entry {
+                title: entry.title,
+                authors: entry.authors,
+                platform: platform.map(String::from),
+                quirks,
+                ipf: rom.tickrate.filter(|tickrate| *tickrate > 0),
+                keys,
+            }));
+        }
+    }
+    return Ok(None);
+}
+
+// The database's platforms as the ones here, None for ones that can not be run
+fn platform_name(platform: &str) -> Option<&'static str> {
+    return match platform {
+        "originalChip8" | "hybridVIP" => Some("vip"),
+        "modernChip8" => Some("modern"),
+        "chip48" | "superchip1" | "superchip" => Some("schip"),
+        "xochip" => Some("xochip"),
+        _ => None,
+    };
+}
+
+// The database's quirk names are the opposite way round for wrap
+fn quirk_list(quirks: &HashMap<String, bool>) -> String {
+    let mut list = Vec::new();
+    for (name, on) in quirks {
+        let (quirk, on) = match name.as_str() {
+            "shift" => ("shift", *on),
+            "memoryLeaveIUnchanged" => ("load_store", *on),
+            "jump" => ("jump", *on),
+            "logic" => ("vf_reset", *on),
+            "wrap" => ("clip", !*on),
+            "vblank" => ("display_wait", *on),
+            _ => continue,
+        };
+        list.push(if on { quirk.to_string() } else { format!("-{}", quirk) });
+    }
+    list.sort();
+    return list.join(",");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+
+    fn database() -> String {
+        return format!(r#"[
+            {{"title": "Other", "roms": {{"0000000000000000000000000000000000000000": {{"platforms": ["xochip"]}}}}}},
+            {{
+                "title": "Clear",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "platforms": ["megachip8", "superchip", "originalChip8"],
+                        "tickrate": 30,
+                        "quirkyPlatforms": {{"superchip": {{"wrap": true, "shift": false}}}},
+                        "keys": {{"up": 5, "a": 6, "left": 20}}
+                    }}
+                }}
+            }}
+        ]"#, settings::sha1(&PROGRAM));
+    }
+
+    #[test]
+    fn finds_a_program_by_its_hash() {
+        let entry = find(&database(), &PROGRAM).unwrap().unwrap();
+        assert_eq!(entry.title, "Clear");
+        assert_eq!(entry.authors, vec!["Someone"]);
+        assert_eq!(entry.platform.as_deref(), Some("schip"));
+        assert_eq!(entry.quirks.as_deref(), Some("-clip,-shift"));
+        assert_eq!(entry.ipf, Some(30));
+        assert_eq!(entry.keys, vec![(String::from("a"), 6), (String::from("up"), 5)]);
+    }
+
+    #[test]
+    fn unknown_programs_are_not_found() {
+        assert!(find(&database(), &[0x12, 0x00]).unwrap().is_none());
+        assert!(find("{", &PROGRAM).is_err());
+    }
+
+    #[test]
+    fn modern_chip8_has_only_clipping() {
+        let platform = platform_name("modernChip8").unwrap();
+        assert_eq!(crate::cpu::Quirks::platform(platform).unwrap().describe(), "clip");
+    }
+
+    // IBM Logo.ch8, the first program in the built in database
+    const IBM_LOGO: [u8; 132] = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0, 0x1F,
+        0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x66,
+        0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00,
+        0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F,
+        0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00,
+        0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B,
+        0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+        0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+        0x00, 0xE0, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn bundled_database_knows_the_ibm_logo() {
+        let entry = find(BUNDLED, &IBM_LOGO).unwrap().unwrap();
+        assert_eq!(entry.title, "IBM Logo");
+        assert_eq!(entry.platform.as_deref(), Some("vip"));
+        assert_eq!(entry.quirks, None);
+        assert_eq!(entry.quirks().unwrap().describe(), crate::cpu::Quirks::platform("vip").unwrap().describe());
+        assert!(find(BUNDLED, &PROGRAM).unwrap().is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use super::cpu::Cpu;
+use super::opcode::Opcode;
+use std::collections::BTreeSet;
+
+const START: usize = 0x200;
+
+/*
+    Turns a program back into the assembly assembler.rs reads, one instruction
+    for every two bytes from 0x200 on. Programs mix code and sprites, so this
+    can not tell them apart and the sprites come out as whatever instructions
+    they happen to look like, or as DW when they are not instructions at all.
+    Either way the listing assembles back into exactly the same bytes. Jumps,
+    calls and LD I get labels when they point at the start of a line
+*/
+pub fn disassemble(program: &[u8]) -> String {
+    let targets = labels(program);
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < program.len() {
+        let address = START + offset;
+        if targets.contains(&address) {
+            out.push_str(&format!("{}:\n", label(address)));
+        }
+        let (text, hex) = if offset + 1 < program.len() {
+            let opcode = [program[offset], program[offset + 1]];
+            let text = instruction(&opcode).map(|text| with_label(&text, &opcode, &targets));
+            (text.unwrap_or_else(|| format!("DW #{:02X}{:02X}", opcode[0], opcode[1])), format!("{:02X}{:02X}", opcode[0], opcode[1]))
+        } else {
+            (format!("DB #{:02X}", program[offset]), format!("{:02X}", program[offset]))
+        };
+        out.push_str(&format!("    {:<20}; {:03X}: {}\n", text, address, hex));
+        offset += 2;
+    }
+    return out;
+}
+
+// The instruction for an opcode, None when it is not one the Cpu knows
+pub fn instruction(opcode: &[u8; 2]) -> Option<String> {
+    let op = Opcode::new(opcode);
+    let [a, x, y, n] = op.digits;
+    let nnn = (x << 8) | (y << 4) | n;
+    let kk = (y << 4) | n;
+    let text = match (a, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => String::from("CLS"),
+        (0x0, 0x0, 0xE, 0xE) => String::from("RET"),
+        (0x0, _, _, _) => format!("SYS #{:03X}", nnn),
+        (0x1, _, _, _) => format!("JP #{:03X}", nnn),
+        (0x2, _, _, _) => format!("CALL #{:03X}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, #{:02X}", x, kk),
+        (0x4, _, _, _) => format!("SNE V{:X}, #{:02X}", x, kk),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, #{:02X}", x, kk),
+        (0x7, _, _, _) => format!("ADD V{:X}, #{:02X}", x, kk),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, #{:03X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, #{:03X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, #{:02X}", x, kk),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, 0x0, 0x0, 0x2) => String::from("AUDIO"),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x3, 0xA) => format!("PITCH V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        _ => return None,
+    };
+    return Some(text);
+}
+
+// The addresses jumps, calls and LD I point at, if they are the start of a line
+fn labels(program: &[u8]) -> BTreeSet<usize> {
+    let mut targets = BTreeSet::new();
+    for opcode in program.chunks_exact(2) {
+        if let Some(address) = target(&[opcode[0], opcode[1]]) {
+            if address >= START && address < START + program.len() && (address - START).is_multiple_of(2) {
+                targets.insert(address);
+            }
+        }
+    }
+    return targets;
+}
+
+fn target(opcode: &[u8; 2]) -> Option<usize> {
+    let address = usize::from(opcode[0] & 0x0F) << 8 | usize::from(opcode[1]);
+    return match opcode[0] >> 4 {
+        0x1 | 0x2 | 0xA | 0xB => Some(address),
+        _ => None,
+    };
+}
+
+fn with_label(text: &str, opcode: &[u8; 2], targets: &BTreeSet<usize>) -> String {
+    return match target(opcode) {
+        Some(address) if targets.contains(&address) => text.replace(&format!("#{:03X}", address), &label(address)),
+        _ => text.to_string(),
+    };
+}
+
+fn label(address: usize) -> String {
+    return format!("L{:03X}", address);
+}
+
+// What is worth knowing about a program before running it
+pub fn describe(program: &[u8]) -> Vec<String> {
+    let free = Cpu::new().memory.len() - START;
+    let mut lines = vec![format!("Size: {} bytes ({} of {} bytes of memory free)", program.len(), free.saturating_sub(program.len()), free)];
+    let (mut xo_chip, mut super_chip, mut unknown) = (0, 0, 0);
+    for opcode in program.chunks_exact(2) {
+        let opcode = [opcode[0], opcode[1]];
+        match instruction(&opcode) {
+            _ if is_super_chip(&opcode) => super_chip += 1,
+            Some(text) if text == "AUDIO" || text.starts_with("PITCH") => xo_chip += 1,
+            Some(_) => {},
+            None => unknown += 1,
+        }
+    }
+    lines.push(format!("Jumps, calls and LD I to {} places in the program", labels(program).len()));
+    if xo_chip > 0 {
+        lines.push(format!("Uses XO-CHIP audio in {} places, try --platform xochip", xo_chip));
+    }
+    if super_chip > 0 {
+        lines.push(format!("Has {} SUPER-CHIP instructions, which this interpreter does not run (they may be sprites)", super_chip));
+    }
+    lines.push(format!("Words that are not instructions: {} (usually sprites)", unknown));
+    return lines;
+}
+
+// Scrolling, hires, exit, big font and flag registers
+fn is_super_chip(opcode: &[u8; 2]) -> bool {
+    return match (opcode[0], opcode[1]) {
+        (0x00, 0xFB..=0xFF) | (0x00, 0xC1..=0xCF) => true,
+        (high, 0x30) | (high, 0x75) | (high, 0x85) => high >> 4 == 0xF,
+        _ => false,
+    };
+}
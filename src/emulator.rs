@@ -0,0 +1,95 @@
+use super::beeper::Tone;
+use super::cpu::Cpu;
+use super::database::{self, Entry};
+use super::framebuffer::FrameBuffer;
+use super::palette::Palette;
+use super::screenshot;
+use std::path::Path;
+
+// Roughly how many instructions the original COSMAC VIP got through in a 60hz frame
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+pub const MAX_INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+/*
+    Drives the Cpu one 60hz frame at a time. Each frame runs a fixed number of
+    instructions and then ticks the timers, so emulation speed is set by how
+    often run_frame is called rather than by how fast the host is
+*/
+pub struct Emulator {
+    pub cpu: Cpu,
+    pub instructions_per_frame: u32,
+    pub frame_count: u64,
+    pub instructions_executed: u64,     // Not counting cycles spent waiting for a key or the next frame
+    pub beeping: bool,                  // The sound timer was running in the last frame
+}
+
+impl Emulator {
+    pub fn new(instructions_per_frame: u32) -> Emulator {
+        return Emulator {
+            cpu: Cpu::new(),
+            instructions_per_frame,
+            frame_count: 0,
+            instructions_executed: 0,
+            beeping: false,
+        };
+    }
+
+    // A fresh emulator running the program, at the speed and with the quirks the
+    // database has for it, and the database's entry for the title and keys. A
+    // database that can not be read is passed over as if it did not know the program
+    pub fn load(program: &[u8]) -> Result<(Emulator, Option<Entry>), String> {
+        let entry = database::lookup(program).unwrap_or(None);
+        let mut chip8 = Emulator::new(DEFAULT_INSTRUCTIONS_PER_FRAME);
+        if let Some(entry) = &entry {
+            chip8.cpu.quirks = entry.quirks()?;
+            if let Some(ipf) = entry.ipf {
+                chip8.instructions_per_frame = ipf.clamp(1, MAX_INSTRUCTIONS_PER_FRAME);
+            }
+        }
+        chip8.cpu.load_sprites();
+        chip8.cpu.load_bytes(program)?;
+        return Ok((chip8, entry));
+    }
+
+    // Raise or lower the instructions per frame by about 10% (at least 1)
+    pub fn change_speed(self: &mut Self, faster: bool) {
+        let step = (self.instructions_per_frame / 10).max(1);
+        self.instructions_per_frame = if faster {
+            (self.instructions_per_frame + step).min(MAX_INSTRUCTIONS_PER_FRAME)
+        } else {
+            self.instructions_per_frame.saturating_sub(step).max(1)
+        };
+    }
+
+    // Save the display as a PNG without needing a window, scale 1 is the native resolution
+    pub fn screenshot(self: &Self, path: &Path, palette: &Palette, scale: usize) -> Result<(), String> {
+        let (width, height) = self.cpu.resolution();
+        let mut native = FrameBuffer { width, height, pixels: Vec::new() };
+        palette.colorize(&(self.cpu.display), &mut native.pixels);
+        let mut scaled = FrameBuffer::default();
+        native.upscale(scale.max(1), &mut scaled);
+        return screenshot::save_png(path, &scaled);
+    }
+
+    // What the beeper should play for the last frame
+    pub fn tone(self: &Self) -> Tone {
+        return Tone {
+            beeping: self.beeping,
+            pattern: self.cpu.audio_pattern,
+            pitch: self.cpu.pitch,
+        };
+    }
+
+    pub fn run_frame(self: &mut Self) {
+        self.cpu.waiting_for_vblank = false;
+        for _ in 0..self.instructions_per_frame {
+            if self.cpu.cycle() {
+                self.instructions_executed += 1;
+            }
+        }
+        // Checked before the timers tick so a sound timer of 1 still beeps for a frame
+        self.beeping = self.cpu.sound_timer > 0;
+        self.cpu.tick_timers();
+        self.frame_count += 1;
+    }
+}
@@ -0,0 +1,188 @@
+use super::framebuffer::FrameBuffer;
+
+pub const FILTERS: [&str; 5] = ["none", "scanlines", "shadow-mask", "bloom", "lcd"];
+
+/*
+    Post processing done in software on the upscaled image, so they look the same
+    in the window as in screenshots and recordings. Every filter works in cells of
+    scale x scale output pixels, one for each pixel of the display
+*/
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    None,
+    Scanlines,      // Darkens the bottom rows of every pixel like the gaps between CRT lines
+    ShadowMask,     // Red, green and blue stripes like a CRT's aperture grille
+    Bloom,          // Bright pixels glow into their neighbours
+    Lcd,            // A grid between pixels like an old handheld's LCD
+}
+
+impl Filter {
+    pub fn parse(value: &str) -> Result<Filter, String> {
+        return match value {
+            "none" => Ok(Filter::None),
+            "scanlines" => Ok(Filter::Scanlines),
+            "shadow-mask" => Ok(Filter::ShadowMask),
+            "bloom" => Ok(Filter::Bloom),
+            "lcd" => Ok(Filter::Lcd),
+            _ => Err(format!("Filter '{}' must be one of {}", value, FILTERS.join(", "))),
+        };
+    }
+
+    pub fn apply(self: &Self, frame: &mut FrameBuffer, scale: usize) {
+        match self {
+            Filter::None => {},
+            Filter::Scanlines => scanlines(frame, scale),
+            Filter::ShadowMask => shadow_mask(frame),
+            Filter::Bloom => bloom(frame, scale),
+            Filter::Lcd => lcd_grid(frame, scale),
+        }
+    }
+}
+
+fn darken(pixel: &mut [u8], amount: f32) {
+    for channel in pixel.iter_mut().take(3) {
+        *channel = (f32::from(*channel) * amount) as u8;
+    }
+}
+
+fn scanlines(frame: &mut FrameBuffer, scale: usize) {
+    let dark_rows = (scale / 3).max(1);         // A third of every pixel, at least one row
+    let width = frame.width;
+    for (y, row) in frame.pixels.chunks_mut(width * 4).enumerate() {
+        if y % scale >= scale - dark_rows {
+            row.chunks_mut(4).for_each(|pixel| darken(pixel, 0.5));
+        }
+    }
+}
+
+fn shadow_mask(frame: &mut FrameBuffer) {
+    let width = frame.width;
+    for (i, pixel) in frame.pixels.chunks_mut(4).enumerate() {
+        let stripe = (i % width) % 3;           // Which of red, green or blue this column shows
+        for (channel, value) in pixel.iter_mut().take(3).enumerate() {
+            if channel != stripe {
+                *value = (f32::from(*value) * 0.6) as u8;
+            }
+        }
+    }
+}
+
+fn lcd_grid(frame: &mut FrameBuffer, scale: usize) {
+    if scale < 3 {
+        return;                                 // No room for a gap
+    }
+    let width = frame.width;
+    for (y, row) in frame.pixels.chunks_mut(width * 4).enumerate() {
+        for (x, pixel) in row.chunks_mut(4).enumerate() {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                darken(pixel, 0.8);
+            }
+        }
+    }
+}
+
+// Blur a copy of the frame with a box blur and add it back on top
+fn bloom(frame: &mut FrameBuffer, scale: usize) {
+    let radius = (scale / 2).max(1);
+    let glow = box_blur_vertical(&box_blur_horizontal(frame, radius), radius);
+    for (pixel, glow) in frame.pixels.chunks_mut(4).zip(glow.pixels.chunks(4)) {
+        for (value, glow) in pixel.iter_mut().zip(glow).take(3) {
+            *value = value.saturating_add(glow / 2);
+        }
+    }
+}
+
+fn box_blur_horizontal(frame: &FrameBuffer, radius: usize) -> FrameBuffer {
+    let mut out = frame.clone();
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let start = x.saturating_sub(radius);
+            let end = (x + radius).min(frame.width - 1);
+            let mut sum = [0u32; 3];
+            for sx in start..=end {
+                add_pixel(&mut sum, &frame.pixel(sx, y));
+            }
+            let count = (end - start + 1) as u32;
+            let i = (y * frame.width + x) * 4;
+            set_average(&mut out.pixels[i..i + 3], &sum, count);
+        }
+    }
+    return out;
+}
+
+fn box_blur_vertical(frame: &FrameBuffer, radius: usize) -> FrameBuffer {
+    let mut out = frame.clone();
+    for y in 0..frame.height {
+        let start = y.saturating_sub(radius);
+        let end = (y + radius).min(frame.height - 1);
+        let count = (end - start + 1) as u32;
+        for x in 0..frame.width {
+            let mut sum = [0u32; 3];
+            for sy in start..=end {
+                add_pixel(&mut sum, &frame.pixel(x, sy));
+            }
+            let i = (y * frame.width + x) * 4;
+            set_average(&mut out.pixels[i..i + 3], &sum, count);
+        }
+    }
+    return out;
+}
+
+fn add_pixel(sum: &mut [u32; 3], pixel: &[u8; 4]) {
+    for (total, value) in sum.iter_mut().zip(pixel) {
+        *total += u32::from(*value);
+    }
+}
+
+fn set_average(pixel: &mut [u8], sum: &[u32; 3], count: u32) {
+    for (value, total) in pixel.iter_mut().zip(sum) {
+        *value = (total / count) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white(width: usize, height: usize) -> FrameBuffer {
+        return FrameBuffer { width, height, pixels: vec![0xFF; width * height * 4] };
+    }
+
+    // The red of every pixel, row by row
+    fn reds(frame: &FrameBuffer) -> Vec<u8> {
+        return frame.pixels.chunks(4).map(|pixel| pixel[0]).collect();
+    }
+
+    #[test]
+    fn scanlines_darken_the_bottom_of_every_pixel() {
+        let mut frame = white(2, 6);
+        Filter::Scanlines.apply(&mut frame, 3);
+        assert_eq!(reds(&frame), [255, 255, 255, 255, 127, 127, 255, 255, 255, 255, 127, 127]);
+        assert!(frame.pixels.chunks(4).all(|pixel| pixel[3] == 0xFF));
+    }
+
+    #[test]
+    fn shadow_mask_keeps_one_color_a_column() {
+        let mut frame = white(4, 1);
+        Filter::ShadowMask.apply(&mut frame, 1);
+        assert_eq!(frame.pixels, [255, 153, 153, 255, 153, 255, 153, 255, 153, 153, 255, 255, 255, 153, 153, 255]);
+    }
+
+    #[test]
+    fn lcd_darkens_the_gaps_between_pixels() {
+        let mut frame = white(3, 3);
+        Filter::Lcd.apply(&mut frame, 3);
+        assert_eq!(reds(&frame), [255, 255, 204, 255, 255, 204, 204, 204, 204]);
+        let mut small = white(2, 2);
+        Filter::Lcd.apply(&mut small, 2);
+        assert_eq!(small.pixels, white(2, 2).pixels);
+    }
+
+    #[test]
+    fn bloom_glows_into_the_neighbours() {
+        let mut frame = FrameBuffer { width: 3, height: 1, pixels: vec![0, 0, 0, 0xFF, 200, 200, 200, 0xFF, 0, 0, 0, 0xFF] };
+        Filter::Bloom.apply(&mut frame, 2);
+        // Blurred over 2 and 3 pixels the glow is 100 and 66, half of it is added
+        assert_eq!(frame.pixels, [50, 50, 50, 0xFF, 233, 233, 233, 0xFF, 50, 50, 50, 0xFF]);
+    }
+}
@@ -0,0 +1,55 @@
+/*
+    An RGBA image, 4 bytes per pixel. This is what the display turns into after
+    going through the palette, and what the filters, the window, screenshots and
+    recordings all work on
+*/
+#[derive(Clone, Default)]
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn size(self: &Self) -> (u32, u32) {
+        return (self.width as u32, self.height as u32);
+    }
+
+    pub fn pixel(self: &Self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        return [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]];
+    }
+
+    // Nearest neighbour scaling by a whole number into another buffer
+    pub fn upscale(self: &Self, factor: usize, out: &mut FrameBuffer) {
+        out.width = self.width * factor;
+        out.height = self.height * factor;
+        out.pixels.clear();
+        for y in 0..self.height {
+            let row = &self.pixels[y * self.width * 4..(y + 1) * self.width * 4];
+            let start = out.pixels.len();
+            for pixel in row.chunks(4) {
+                for _ in 0..factor {
+                    out.pixels.extend_from_slice(pixel);
+                }
+            }
+            for _ in 1..factor {                    // Repeat the finished row
+                out.pixels.extend_from_within(start..start + out.width * 4);
+            }
+        }
+    }
+
+    // Nearest neighbour scaling to any size into another buffer
+    pub fn resample(self: &Self, width: usize, height: usize, out: &mut FrameBuffer) {
+        out.width = width;
+        out.height = height;
+        out.pixels.clear();
+        for y in 0..height {
+            let source_y = y * self.height / height.max(1);
+            for x in 0..width {
+                let i = (source_y * self.width + x * self.width / width.max(1)) * 4;
+                out.pixels.extend_from_slice(&self.pixels[i..i + 4]);
+            }
+        }
+    }
+}
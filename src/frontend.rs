@@ -0,0 +1,181 @@
+use super::beeper::Tone;
+use super::framebuffer::FrameBuffer;
+use std::collections::VecDeque;
+
+/*
+    What a frontend has to provide to run a program: somewhere to show the frames,
+    something to beep with and a source of input. The run loop (runner.rs) only
+    talks to these traits, so a new frontend is just a new set of implementations.
+    Null ones are here for running without a window, and capturing ones so tests
+    can check what was drawn, beeped and pressed
+*/
+
+// Everything the run loop reacts to, frontends translate their own events into these
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    KeyDown(u8),            // A key on the hex keypad, 0 to F
+    KeyUp(u8),
+    Turbo(usize, bool),     // One of the keymap's turbo buttons, true while held
+    Macro(usize),           // Play one of the keymap's macros
+    Quit,
+    TogglePause,
+    Step,                   // Run one frame while paused
+    FastForward(bool),      // True while the fast forward key is held
+    Faster,
+    Slower,
+    ToggleFullscreen,
+    NextUpscaler,
+    ToggleStats,
+    ToggleKeypad,
+    ToggleRecording,
+    SaveSettings,           // Keep the speed and upscaler for the next time the program runs
+    Screenshot,             // What is on screen
+    NativeScreenshot,       // The display at its native resolution
+    Redraw,                 // The window was resized or uncovered
+    PointerDown(u64, f32, f32),     // The mouse button or a finger went down, at a fraction of the window's width and height
+    PointerMotion(u64, f32, f32),   // A pointer that is down moved
+    PointerUp(u64),
+}
+
+pub trait VideoSink {
+    fn present(&mut self, frame: &FrameBuffer);
+
+    // The window title for windowed frontends, it changes with the speed
+    fn set_title(&mut self, _title: &str) {}
+
+    fn toggle_fullscreen(&mut self) {}
+
+    // The pixel of the last frame at a point given as fractions of the window's
+    // size, None if the frame is not there. Only frontends with a pointer need it
+    fn frame_position(&self, _x: f32, _y: f32) -> Option<(usize, usize)> {
+        return None;
+    }
+
+    // Whether the on-screen display should be drawn over the frames, frontends
+    // that show the display at its native size have no room for it
+    fn overlay(&self) -> bool {
+        return true;
+    }
+}
+
+pub trait AudioSink {
+    // Called once per pass of the run loop with what the beeper should be playing
+    fn set_tone(&mut self, tone: &Tone);
+}
+
+pub trait InputSource {
+    // Add every event since the last poll, called once per pass of the run loop
+    fn poll(&mut self, events: &mut Vec<InputEvent>);
+}
+
+// Throws the frames away, for running without a window
+pub struct NullVideo;
+
+impl VideoSink for NullVideo {
+    fn present(&mut self, _frame: &FrameBuffer) {}
+
+    fn overlay(&self) -> bool {
+        return false;
+    }
+}
+
+// Plays nothing, for running without sound hardware
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_tone(&mut self, _tone: &Tone) {}
+}
+
+// Never presses anything, the run loop needs a frame limit to ever stop
+pub struct NullInput;
+
+impl InputSource for NullInput {
+    fn poll(&mut self, _events: &mut Vec<InputEvent>) {}
+}
+
+// Keeps a copy of every frame it is shown
+#[derive(Default)]
+pub struct CapturedVideo {
+    pub frames: Vec<FrameBuffer>,
+    pub title: String,
+}
+
+impl VideoSink for CapturedVideo {
+    fn present(&mut self, frame: &FrameBuffer) {
+        self.frames.push(frame.clone());
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+}
+
+// Keeps the tone of every pass
+#[derive(Default)]
+pub struct CapturedAudio {
+    pub passes: Vec<Tone>,
+}
+
+impl CapturedAudio {
+    // How many separate beeps there were
+    pub fn beeps(self: &Self) -> usize {
+        let mut last = false;
+        let mut beeps = 0;
+        for tone in &self.passes {
+            if tone.beeping && !last {
+                beeps += 1;
+            }
+            last = tone.beeping;
+        }
+        return beeps;
+    }
+}
+
+impl AudioSink for CapturedAudio {
+    fn set_tone(&mut self, tone: &Tone) {
+        self.passes.push(*tone);
+    }
+}
+
+// Plays back a list of events for every pass, then quits once they run out
+pub struct ScriptedInput {
+    passes: VecDeque<Vec<InputEvent>>,
+}
+
+impl ScriptedInput {
+    pub fn new(passes: Vec<Vec<InputEvent>>) -> ScriptedInput {
+        return ScriptedInput { passes: passes.into() };
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, events: &mut Vec<InputEvent>) {
+        match self.passes.pop_front() {
+            Some(pass) => events.extend(pass),
+            None => events.push(InputEvent::Quit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    // A beep lasts as many frames as the sound timer was set to
+    #[test]
+    fn captured_audio_follows_the_sound_timer() {
+        let mut chip8 = Emulator::new(11);
+        // LD V3, 3 then LD ST, V3 and loop
+        chip8.cpu.load_bytes(&[0x63, 0x03, 0xF3, 0x18, 0x12, 0x04]).unwrap();
+        let mut audio = CapturedAudio::default();
+        for _ in 0..5 {
+            chip8.run_frame();
+            audio.set_tone(&chip8.tone());
+        }
+        let beeping: Vec<bool> = audio.passes.iter().map(|tone| tone.beeping).collect();
+        assert_eq!(beeping, [true, true, true, false, false]);
+        assert_eq!(audio.beeps(), 1);
+        assert_eq!(chip8.cpu.sound_timer, 0);
+    }
+}
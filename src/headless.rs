@@ -0,0 +1,66 @@
+use super::options::Options;
+use chip8::emulator::Emulator;
+use chip8::filter::Filter;
+use chip8::frontend::{CapturedAudio, NullAudio, NullInput, NullVideo};
+use chip8::movie::{Movie, MoviePlayer};
+use chip8::phosphor::Phosphor;
+use chip8::record::Format;
+use chip8::render::Renderer;
+use chip8::runner::Runner;
+use chip8::upscale::Upscaler;
+
+// chip8 render: run the program without a window, feeding it the keyboard from a
+// movie, and record every frame to the output file. The movie's seed, speed and
+// quirks are used so the run comes out the same as the one that was recorded
+pub fn render(options: &Options, mut chip8: Emulator) -> Result<(), String> {
+    let output = options.output.as_ref().ok_or("render needs an output file, e.g. -o out.gif")?;
+    let format = Format::from_path(output)?;
+    let movie = match &options.movie {
+        Some(path) => Movie::load(path)?,
+        None => Movie::default(),
+    };
+    movie.apply(&mut chip8);
+    let frames = options.frames.unwrap_or(movie.frames.len() as u64);
+    if frames == 0 {
+        return Err(String::from("Nothing to render, use --frames N or give a --movie"));
+    }
+
+    let phosphor = options.phosphor.map(Phosphor::new);
+    let renderer = Renderer::new(options.palette.clone(), phosphor, options.filter, options.upscaler, options.scale as usize);
+    let mut runner = Runner::new(chip8, renderer, &options.program);
+    runner.throttle = false;
+    runner.frame_limit = Some(frames);
+    runner.start_recording(output, format)?;
+    return runner.run(&mut NullVideo, &mut NullAudio, &mut MoviePlayer::new(movie));
+}
+
+// chip8 run --headless: run the program for a number of frames as fast as it goes,
+// with the keyboard from a movie if there is one, then print the display as text
+pub fn run(options: &Options, mut chip8: Emulator) -> Result<(), String> {
+    let movie = match &options.movie {
+        Some(path) => Some(Movie::load(path)?),
+        None => None,
+    };
+    if let Some(movie) = &movie {
+        movie.apply(&mut chip8);
+    }
+    let frames = options.frames.unwrap_or_else(|| movie.as_ref().map_or(0, |movie| movie.frames.len() as u64));
+
+    let renderer = Renderer::new(options.palette.clone(), None, Filter::None, Upscaler::None, 1);
+    let mut runner = Runner::new(chip8, renderer, &options.program);
+    runner.throttle = false;
+    runner.frame_limit = Some(frames);
+    let mut audio = CapturedAudio::default();
+    match movie {
+        Some(movie) => runner.run(&mut NullVideo, &mut audio, &mut MoviePlayer::new(movie))?,
+        None => runner.run(&mut NullVideo, &mut audio, &mut NullInput)?,
+    }
+
+    let cpu = &runner.chip8.cpu;
+    let (width, height) = cpu.resolution();
+    for row in cpu.display.chunks(width).take(height) {
+        println!("{}", row.iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }).collect::<String>());
+    }
+    println!("{} frames, {} beeps", frames, audio.beeps());
+    return Ok(());
+}
@@ -0,0 +1,468 @@
+use super::frontend::InputEvent;
+use super::turbo::{Macro, Turbo};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/*
+    Which host keys press which keys of the hex keypad. Host keys are named by
+    scancode, SDL's names for where a key is on the keyboard ("Q", "1", "Keypad 8",
+    "Up") rather than what is printed on it, so the layouts stay in the same place
+    on AZERTY and Dvorak keyboards. A hex key can have any number of host keys.
+    Game controllers are mapped the same way by SDL's button names ("a", "dpup",
+    "leftshoulder") and stick directions ("leftx-" is left, "lefty+" is down).
+    Keymap files are TOML, starting from a preset and overriding keys, for every
+    program or just the ones named under [rom], as every game moves with
+    different keys:
+
+        preset = "vip"
+        [keys]
+        2 = ["2", "Up"]             # Replaces the preset's keys for 2
+        8 = ["S", "Down"]
+        [pad]
+        5 = ["a", "b"]
+        [rom.BRIX]                  # Only when playing BRIX
+        keys = { 4 = ["Left"], 6 = ["Right"] }
+        pad = { 4 = ["dpleft", "leftx-"], 6 = ["dpright", "leftx+"] }
+
+    Host keys and pad inputs can also hold a turbo button or play a macro (see
+    turbo.rs), which have their own tables. press is the hex keys they hold:
+
+        [[turbo]]
+        press = "5"
+        frames = 2                  # 2 frames pressed, 2 let go, and so on
+        keys = ["Space"]
+        pad = ["rightshoulder"]
+        [[macro]]
+        keys = ["G"]
+        steps = [{ press = "4", frames = 3 }, { press = "", frames = 1 }, { press = "45", frames = 3 }]
+*/
+
+pub const PRESETS: [&str; 3] = ["hex", "vip", "numpad"];
+
+// The 4x4 block of keys under 1 to 4, top row first
+const BLOCK: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"];
+
+// The COSMAC VIP's keypad, which most games were written for
+const VIP_LAYOUT: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+// The digits where they are printed, so 2 4 6 8 are arrows, and A to F around them
+const NUMPAD: [(&str, u8); 16] = [
+    ("Keypad 0", 0x0), ("Keypad 1", 0x1), ("Keypad 2", 0x2), ("Keypad 3", 0x3),
+    ("Keypad 4", 0x4), ("Keypad 5", 0x5), ("Keypad 6", 0x6), ("Keypad 7", 0x7),
+    ("Keypad 8", 0x8), ("Keypad 9", 0x9), ("Keypad .", 0xA), ("Keypad Enter", 0xB),
+    ("Keypad +", 0xC), ("Keypad -", 0xD), ("Keypad *", 0xE), ("Keypad /", 0xF),
+];
+
+// SDL's names for the scancodes, the host keys a keymap can use. The terminal
+// frontend goes by the letters, digits and punctuation among them
+pub const HOST_KEYS: [&str; 220] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W",
+    "X", "Y", "Z", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "Return", "Escape", "Backspace", "Tab", "Space",
+    "-", "=", "[", "]", "\\", "#", ";", "'", "`", ",", ".", "/", "CapsLock", "F1", "F2", "F3", "F4", "F5", "F6", "F7",
+    "F8", "F9", "F10", "F11", "F12", "PrintScreen", "ScrollLock", "Pause", "Insert", "Home", "PageUp", "Delete",
+    "End", "PageDown", "Right", "Left", "Down", "Up", "Numlock", "Keypad /", "Keypad *", "Keypad -", "Keypad +",
+    "Keypad Enter", "Keypad 1", "Keypad 2", "Keypad 3", "Keypad 4", "Keypad 5", "Keypad 6", "Keypad 7", "Keypad 8",
+    "Keypad 9", "Keypad 0", "Keypad .", "Application", "Power", "Keypad =", "F13", "F14", "F15", "F16", "F17", "F18",
+    "F19", "F20", "F21", "F22", "F23", "F24", "Execute", "Help", "Menu", "Select", "Stop", "Again", "Undo", "Cut",
+    "Copy", "Paste", "Find", "Mute", "VolumeUp", "VolumeDown", "Keypad ,", "Keypad = (AS400)", "AltErase", "SysReq",
+    "Cancel", "Clear", "Prior", "Separator", "Out", "Oper", "Clear / Again", "CrSel", "ExSel", "Keypad 00",
+    "Keypad 000", "ThousandsSeparator", "DecimalSeparator", "CurrencyUnit", "CurrencySubUnit", "Keypad (", "Keypad )",
+    "Keypad {", "Keypad }", "Keypad Tab", "Keypad Backspace", "Keypad A", "Keypad B", "Keypad C", "Keypad D",
+    "Keypad E", "Keypad F", "Keypad XOR", "Keypad ^", "Keypad %", "Keypad <", "Keypad >", "Keypad &", "Keypad &&",
+    "Keypad |", "Keypad ||", "Keypad :", "Keypad #", "Keypad Space", "Keypad @", "Keypad !", "Keypad MemStore",
+    "Keypad MemRecall", "Keypad MemClear", "Keypad MemAdd", "Keypad MemSubtract", "Keypad MemMultiply",
+    "Keypad MemDivide", "Keypad +/-", "Keypad Clear", "Keypad ClearEntry", "Keypad Binary", "Keypad Octal",
+    "Keypad Decimal", "Keypad Hexadecimal", "Left Ctrl", "Left Shift", "Left Alt", "Left GUI", "Right Ctrl",
+    "Right Shift", "Right Alt", "Right GUI", "ModeSwitch", "AudioNext", "AudioPrev", "AudioStop", "AudioPlay",
+    "AudioMute", "MediaSelect", "WWW", "Mail", "Calculator", "Computer", "AC Search", "AC Home", "AC Back",
+    "AC Forward", "AC Stop", "AC Refresh", "AC Bookmarks", "BrightnessDown", "BrightnessUp", "DisplaySwitch",
+    "KBDIllumToggle", "KBDIllumDown", "KBDIllumUp", "Eject", "Sleep", "App1", "App2",
+];
+
+// SDL's names for the buttons, and its axis names with the way they are pushed.
+// The triggers only go one way
+pub const PAD_INPUTS: [&str; 31] = [
+    "a", "b", "x", "y", "back", "guide", "start", "leftstick", "rightstick", "leftshoulder", "rightshoulder",
+    "dpup", "dpdown", "dpleft", "dpright", "misc1", "paddle1", "paddle2", "paddle3", "paddle4", "touchpad",
+    "leftx-", "leftx+", "lefty-", "lefty+", "rightx-", "rightx+", "righty-", "righty+", "lefttrigger+", "righttrigger+",
+];
+
+// The same as the libretro core's RetroPad, the d-pad and left stick press 2 4 6 8
+const PAD: [(&str, u8); 16] = [
+    ("dpup", 0x2), ("dpdown", 0x8), ("dpleft", 0x4), ("dpright", 0x6),
+    ("lefty-", 0x2), ("lefty+", 0x8), ("leftx-", 0x4), ("leftx+", 0x6),
+    ("a", 0x5), ("b", 0x0), ("x", 0x1), ("y", 0x3),
+    ("leftshoulder", 0x7), ("rightshoulder", 0x9), ("back", 0xE), ("start", 0xF),
+];
+
+// What a host key or pad input does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Key(u8),                // Holds a key on the keypad
+    Turbo(usize),           // Holds one of the keymap's turbo buttons
+    Macro(usize),           // Plays one of the keymap's macros
+}
+
+impl Action {
+    // The input event for pressing or letting go of what it is bound to
+    pub fn event(self: &Self, down: bool) -> Option<InputEvent> {
+        return match (self, down) {
+            (Action::Key(key), true) => Some(InputEvent::KeyDown(*key)),
+            (Action::Key(key), false) => Some(InputEvent::KeyUp(*key)),
+            (Action::Turbo(turbo), held) => Some(InputEvent::Turbo(*turbo, held)),
+            (Action::Macro(number), true) => Some(InputEvent::Macro(*number)),
+            (Action::Macro(_), false) => None,
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: HashMap<String, Action>,  // Lowercase host key name to what it does
+    pad: HashMap<String, Action>,       // Controller button or stick direction to what it does
+    pub turbos: Vec<Turbo>,
+    pub macros: Vec<Macro>,
+}
+
+// A preset and the keys that replace its own, as written in keymap files
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBindings {
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub pad: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub turbo: Vec<TurboBinding>,
+    #[serde(default, rename = "macro")]
+    pub macros: Vec<MacroBinding>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurboBinding {
+    pub press: String,
+    #[serde(default = "default_turbo_frames")]
+    pub frames: u32,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub pad: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacroBinding {
+    pub steps: Vec<MacroStep>,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub pad: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacroStep {
+    pub press: String,
+    pub frames: u32,
+}
+
+fn default_turbo_frames() -> u32 {
+    return 2;
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    preset: Option<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pad: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    turbo: Vec<TurboBinding>,
+    #[serde(default, rename = "macro")]
+    macros: Vec<MacroBinding>,
+    #[serde(default)]
+    rom: HashMap<String, KeyBindings>,
+}
+
+impl Default for Keymap {
+    // The block under 1 to 4 in hex order, 1 is 0 and V is F
+    fn default() -> Keymap {
+        return Keymap::preset("hex").unwrap();
+    }
+}
+
+impl Keymap {
+    pub fn preset(name: &str) -> Result<Keymap, String> {
+        let pairs: Vec<(&str, u8)> = match name {
+            "hex" => BLOCK.iter().enumerate().map(|(key, name)| (*name, key as u8)).collect(),
+            "vip" => BLOCK.iter().zip(VIP_LAYOUT.iter()).map(|(name, key)| (*name, *key)).collect(),
+            "numpad" => NUMPAD.to_vec(),
+            _ => return Err(format!("Keymap '{}' must be one of {} or a .toml file", name, PRESETS.join(", "))),
+        };
+        let mut keymap = Keymap { bindings: HashMap::new(), pad: HashMap::new(), turbos: Vec::new(), macros: Vec::new() };
+        for (name, key) in pairs {
+            keymap.bindings.insert(name.to_ascii_lowercase(), Action::Key(key));
+        }
+        for (input, key) in PAD.iter() {
+            keymap.pad.insert(input.to_string(), Action::Key(*key));
+        }
+        return Ok(keymap);
+    }
+
+    // One of PRESETS or a keymap file, with the overrides for the program applied
+    pub fn parse(value: &str, program: &str) -> Result<Keymap, String> {
+        if value.to_ascii_lowercase().ends_with(".toml") {
+            return Keymap::load(value, program);
+        }
+        return Keymap::preset(value);
+    }
+
+    pub fn load(path: &str, program: &str) -> Result<Keymap, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        return Keymap::from_toml(&text, program).map_err(|e| format!("{}: {}", path, e));
+    }
+
+    pub fn from_toml(text: &str, program: &str) -> Result<Keymap, String> {
+        let file: KeymapFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let bindings = KeyBindings { preset: file.preset, keys: file.keys, pad: file.pad, turbo: file.turbo, macros: file.macros };
+        let mut keymap = bindings.build(None)?;
+
+        // Programs are matched by file name, without the folder or extension
+        let name = Path::new(program).file_stem().map(|stem| stem.to_string_lossy().to_ascii_lowercase());
+        let overrides = file.rom.iter().find(|(rom, _)| Some(rom.to_ascii_lowercase()) == name);
+        if let Some((_, bindings)) = overrides {
+            keymap = bindings.build(Some(keymap))?;
+        }
+        return Ok(keymap);
+    }
+
+    // Make these host keys the only ones for a hex key, taking them from any other.
+    // They have to be in HOST_KEYS
+    pub fn bind(self: &mut Self, key: u8, host_keys: &[String]) -> Result<(), String> {
+        check_host_keys(host_keys)?;
+        replace(&mut self.bindings, Action::Key(key), host_keys);
+        return Ok(());
+    }
+
+    // The same for controller inputs, which have to be in PAD_INPUTS
+    pub fn bind_pad(self: &mut Self, key: u8, inputs: &[String]) -> Result<(), String> {
+        check_pad_inputs(inputs)?;
+        replace(&mut self.pad, Action::Key(key), inputs);
+        return Ok(());
+    }
+
+    pub fn add_turbo(self: &mut Self, turbo: Turbo, host_keys: &[String], inputs: &[String]) -> Result<(), String> {
+        check_host_keys(host_keys)?;
+        check_pad_inputs(inputs)?;
+        let action = Action::Turbo(self.turbos.len());
+        self.turbos.push(turbo);
+        replace(&mut self.bindings, action, host_keys);
+        replace(&mut self.pad, action, inputs);
+        return Ok(());
+    }
+
+    pub fn add_macro(self: &mut Self, steps: Macro, host_keys: &[String], inputs: &[String]) -> Result<(), String> {
+        check_host_keys(host_keys)?;
+        check_pad_inputs(inputs)?;
+        let action = Action::Macro(self.macros.len());
+        self.macros.push(steps);
+        replace(&mut self.bindings, action, host_keys);
+        replace(&mut self.pad, action, inputs);
+        return Ok(());
+    }
+
+    // Give a hex key more host keys and controller inputs, keeping the ones it has
+    pub fn add(self: &mut Self, key: u8, host_keys: &[&str], inputs: &[&str]) {
+        for name in host_keys {
+            self.bindings.insert(name.to_ascii_lowercase(), Action::Key(key));
+        }
+        for input in inputs {
+            self.pad.insert(input.to_ascii_lowercase(), Action::Key(key));
+        }
+    }
+
+    // What a host key does, names are not case sensitive
+    pub fn action(self: &Self, host_key: &str) -> Option<Action> {
+        return self.bindings.get(&host_key.to_ascii_lowercase()).copied();
+    }
+
+    pub fn pad_action(self: &Self, input: &str) -> Option<Action> {
+        return self.pad.get(&input.to_ascii_lowercase()).copied();
+    }
+
+    // The hex key a host key presses, for frontends without turbo buttons or macros
+    pub fn key(self: &Self, host_key: &str) -> Option<u8> {
+        return match self.action(host_key) {
+            Some(Action::Key(key)) => Some(key),
+            _ => None,
+        };
+    }
+}
+
+impl KeyBindings {
+    // Apply these bindings to a keymap, or to their preset when they have one. A
+    // preset on top of a keymap only replaces the keys it uses, so the keymap's
+    // turbo buttons and macros are kept
+    pub fn build(self: &Self, base: Option<Keymap>) -> Result<Keymap, String> {
+        let mut keymap = match (&self.preset, base) {
+            (Some(preset), Some(mut base)) => {
+                let preset = Keymap::preset(preset)?;
+                base.bindings.extend(preset.bindings);
+                base.pad.extend(preset.pad);
+                base
+            },
+            (Some(preset), None) => Keymap::preset(preset)?,
+            (None, Some(base)) => base,
+            (None, None) => Keymap::default(),
+        };
+        for (key, host_keys) in &self.keys {
+            keymap.bind(hex_key(key)?, host_keys)?;
+        }
+        for (key, inputs) in &self.pad {
+            keymap.bind_pad(hex_key(key)?, inputs)?;
+        }
+        for turbo in &self.turbo {
+            let key = match hex_keys(&turbo.press)? {
+                keyboard if keyboard.count_ones() == 1 => keyboard.trailing_zeros() as u8,
+                _ => return Err(format!("A turbo button presses one key, not '{}'", turbo.press)),
+            };
+            keymap.add_turbo(Turbo { key, frames: turbo.frames.max(1) }, &turbo.keys, &turbo.pad)?;
+        }
+        for binding in &self.macros {
+            let mut steps = Macro::default();
+            for step in &binding.steps {
+                steps.steps.push((hex_keys(&step.press)?, step.frames));
+            }
+            keymap.add_macro(steps, &binding.keys, &binding.pad)?;
+        }
+        return Ok(keymap);
+    }
+}
+
+fn hex_key(name: &str) -> Result<u8, String> {
+    return match u8::from_str_radix(name, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("'{}' is not a key on the keypad, they are 0 to F", name)),
+    };
+}
+
+// Hex keys written one after another, "45" is 4 and 5, as keyboard bits
+fn hex_keys(keys: &str) -> Result<u16, String> {
+    let mut keyboard = 0;
+    for key in keys.chars().filter(|c| !c.is_whitespace()) {
+        keyboard |= 1 << hex_key(&key.to_string())?;
+    }
+    return Ok(keyboard);
+}
+
+fn check_host_keys(host_keys: &[String]) -> Result<(), String> {
+    let known = |name: &String| HOST_KEYS.iter().any(|key| key.eq_ignore_ascii_case(name));
+    return match host_keys.iter().find(|name| !known(name)) {
+        Some(name) => Err(format!("'{}' is not a key, keys have SDL's scancode names like \"Q\", \"Keypad 1\" or \"Left Shift\"", name)),
+        None => Ok(()),
+    };
+}
+
+fn check_pad_inputs(inputs: &[String]) -> Result<(), String> {
+    return match inputs.iter().find(|input| !PAD_INPUTS.contains(&input.to_ascii_lowercase().as_str())) {
+        Some(input) => Err(format!("'{}' is not a controller input, they are {}", input, PAD_INPUTS.join(", "))),
+        None => Ok(()),
+    };
+}
+
+fn replace(bindings: &mut HashMap<String, Action>, action: Action, names: &[String]) {
+    bindings.retain(|_, bound| *bound != action);
+    for name in names {
+        bindings.insert(name.to_ascii_lowercase(), action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        preset = "vip"
+        [keys]
+        2 = ["2", "Up"]
+        [pad]
+        5 = ["x"]
+        [[turbo]]
+        press = "5"
+        frames = 3
+        keys = ["Space"]
+        pad = ["rightshoulder"]
+        [[macro]]
+        keys = ["G"]
+        steps = [{ press = "4", frames = 3 }, { press = "", frames = 1 }, { press = "45", frames = 2 }]
+        [rom.brix]
+        keys = { 4 = ["Left"] }
+        [rom.pong]
+        preset = "numpad"
+    "#;
+
+    #[test]
+    fn presets() {
+        let hex = Keymap::preset("hex").unwrap();
+        assert_eq!((hex.key("1"), hex.key("W"), hex.key("v")), (Some(0x0), Some(0x5), Some(0xF)));
+        let vip = Keymap::preset("vip").unwrap();
+        assert_eq!((vip.key("1"), vip.key("4"), vip.key("X"), vip.key("V")), (Some(0x1), Some(0xC), Some(0x0), Some(0xF)));
+        let numpad = Keymap::preset("numpad").unwrap();
+        assert_eq!((numpad.key("Keypad 8"), numpad.key("keypad enter"), numpad.key("1")), (Some(0x8), Some(0xB), None));
+        assert_eq!(numpad.pad_action("dpup"), Some(Action::Key(0x2)));
+        assert!(Keymap::preset("qwerty").is_err());
+    }
+
+    #[test]
+    fn files_override_their_preset() {
+        let keymap = Keymap::from_toml(FILE, "games/Tetris.ch8").unwrap();
+        assert_eq!(keymap.key("Up"), Some(0x2));
+        assert_eq!(keymap.key("2"), Some(0x2));
+        assert_eq!(keymap.key("Q"), Some(0x4));
+        assert_eq!(keymap.key("Left"), None);
+        assert_eq!(keymap.pad_action("x"), Some(Action::Key(0x5)));
+        assert_eq!(keymap.pad_action("a"), None);           // The keys listed are the only ones
+
+        assert_eq!(keymap.turbos.len(), 1);
+        assert_eq!((keymap.turbos[0].key, keymap.turbos[0].frames), (0x5, 3));
+        assert_eq!(keymap.action("space"), Some(Action::Turbo(0)));
+        assert_eq!(keymap.pad_action("rightshoulder"), Some(Action::Turbo(0)));
+        assert_eq!(keymap.macros[0].steps, vec![(0x0010, 3), (0x0000, 1), (0x0030, 2)]);
+        assert_eq!(keymap.action("G"), Some(Action::Macro(0)));
+    }
+
+    #[test]
+    fn programs_get_their_own_keys() {
+        let brix = Keymap::from_toml(FILE, "/roms/BRIX.ch8").unwrap();
+        assert_eq!(brix.key("Left"), Some(0x4));
+        assert_eq!(brix.key("Q"), None);
+        assert_eq!(brix.key("Up"), Some(0x2));
+
+        // A preset only for one program keeps the turbo buttons and macros
+        let pong = Keymap::from_toml(FILE, "pong.ch8").unwrap();
+        assert_eq!(pong.key("Keypad 1"), Some(0x1));
+        assert_eq!(pong.action("Space"), Some(Action::Turbo(0)));
+        assert_eq!(pong.action("G"), Some(Action::Macro(0)));
+        assert_eq!(pong.turbos.len(), 1);
+        assert_eq!(pong.macros.len(), 1);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let error = Keymap::from_toml("[keys]\n1 = [\"Kp1\"]\n", "").unwrap_err();
+        assert!(error.contains("'Kp1' is not a key"), "{}", error);
+        assert!(Keymap::from_toml("[[turbo]]\npress = \"5\"\nkeys = [\"Spacebar\"]\n", "").is_err());
+        assert!(Keymap::from_toml("[[macro]]\nkeys = [\"Enter\"]\nsteps = []\n", "").is_err());
+        assert!(Keymap::from_toml("[pad]\n1 = [\"start+\"]\n", "").is_err());
+        assert!(Keymap::from_toml("[keys]\nG = [\"Q\"]\n", "").is_err());
+        assert!(Keymap::from_toml("[[turbo]]\npress = \"45\"\n", "").is_err());
+        assert!(Keymap::from_toml("preset = \"hex\"\nkey = {}\n", "").is_err());
+        assert!(Keymap::from_toml("[keys]\n1 = [\"keypad 1\", \"Left Shift\", \"\\\\\"]\n", "").is_ok());
+    }
+}
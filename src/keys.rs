@@ -1,64 +1,234 @@
-use sdl2::keyboard::Keycode;
+use chip8::frontend::{InputEvent, InputSource};
+use chip8::keymap::{Action, Keymap};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::{EventPump, GameControllerSubsystem};
+
+// How far a stick has to be pushed to press a key, out of 32767
+const STICK_DEAD_ZONE: i16 = 16000;
+
+// SDL also turns touches into mouse events from this mouse, the fingers are used instead
+const TOUCH_MOUSE: u32 = u32::MAX;
 
 /*
-    Using the following Keys as a Keyboard
-    | 1 | 2 | 3 | 4 |
-    | Q | W | E | R |
-    | A | S | D | F |
-    | Z | X | C | V |
+    Turns SDL events into the run loop's input events. The keypad goes through the
+    keymap by scancode, the hotkeys stay on the keys with their letters printed on
+    them. Game controllers are opened when SDL says they are plugged in, which it
+    also does at startup for the ones already there, and their keys let go when
+    they are unplugged. The left mouse button is pointer 0 and every finger on a
+    touchscreen is a pointer of its own, for the clickable keypad
 */
-pub fn handle_key_event(key: &sdl2::keyboard::Keycode) -> (u16, u8) {
-    let shift;
-    match key {
-        Keycode::Num1 => {
-            shift = 0;
-        },
-        Keycode::Num2 => {
-            shift = 1;
-        },
-        Keycode::Num3 => {
-            shift = 2;
-        },
-        Keycode::Num4 => {
-            shift = 3;
-        },
-        Keycode::Q => {
-            shift = 4;
-        },
-        Keycode::W => {
-            shift = 5;
-        },
-        Keycode::E => {
-            shift = 6;
-        },
-        Keycode::R => {
-            shift = 7;
-        },
-        Keycode::A => {
-            shift = 8;
-        },
-        Keycode::S => {
-            shift = 9;
-        },
-        Keycode::D => {
-            shift = 10;
-        },
-        Keycode::F => {
-            shift = 11;
-        },
-        Keycode::Z => {
-            shift = 12;
-        },
-        Keycode::X => {
-            shift = 13;
-        },
-        Keycode::C => {
-            shift = 14;
-        },
-        Keycode::V => {
-            shift = 15;
-        },
-        _ => { return (0, 0); }
+pub struct SdlInput {
+    event_pump: EventPump,
+    keymap: Keymap,
+    held: Vec<(String, Action)>,    // Host keys and pad inputs that are down and what each one does
+    controller_subsystem: Option<GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    window_size: (u32, u32),    // Mouse positions are turned into fractions of it
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump, keymap: Keymap, controller_subsystem: Option<GameControllerSubsystem>, window_size: (u32, u32)) -> SdlInput {
+        return SdlInput {
+            event_pump,
+            keymap,
+            held: Vec::new(),
+            controller_subsystem,
+            controllers: Vec::new(),
+            window_size,
+        };
+    }
+
+    fn mouse_position(self: &Self, x: i32, y: i32) -> (f32, f32) {
+        return (x as f32 / self.window_size.0.max(1) as f32, y as f32 / self.window_size.1.max(1) as f32);
+    }
+
+    // A hex key (or turbo button) stays pressed until the last of its host keys held down is released
+    fn keypad(self: &mut Self, host_key: &str, action: Option<Action>, down: bool, events: &mut Vec<InputEvent>) -> bool {
+        let action = match action {
+            Some(action) => action,
+            None => return false,
+        };
+        let was_held = self.held.iter().any(|(_, held)| *held == action);
+        let already_down = self.held.iter().any(|(name, _)| name == host_key);
+        if down && !already_down {
+            self.held.push((host_key.to_string(), action));
+        } else if !down {
+            self.held.retain(|(name, _)| name != host_key);
+        }
+        let is_held = self.held.iter().any(|(_, held)| *held == action);
+        if is_held != was_held {
+            events.extend(action.event(is_held));
+        }
+        return true;
+    }
+
+    fn add_controller(self: &mut Self, index: u32) {
+        if let Some(subsystem) = &self.controller_subsystem {
+            match subsystem.open(index) {
+                Ok(controller) => self.controllers.push(controller),
+                Err(e) => eprintln!("Could not open controller {}: {}", index, e),
+            }
+        }
+    }
+
+    fn remove_controller(self: &mut Self, id: u32, events: &mut Vec<InputEvent>) {
+        self.controllers.retain(|controller| controller.instance_id() != id);
+        let prefix = pad_name(id, "");
+        let inputs: Vec<(String, Action)> = self.held.iter().filter(|(name, _)| name.starts_with(&prefix)).cloned().collect();
+        for (input, action) in inputs {
+            self.keypad(&input, Some(action), false, events);
+        }
+    }
+
+    fn axis(self: &mut Self, id: u32, axis: &str, value: i16, events: &mut Vec<InputEvent>) {
+        for (input, down) in axis_inputs(axis, value) {
+            self.keypad(&pad_name(id, &input), self.keymap.pad_action(&input), down, events);
+        }
+    }
+}
+
+// Stick directions are inputs of their own, leftx- and leftx+ for the two ways the left
+// stick goes sideways, and whether each is pushed past the dead zone. Triggers only have +
+fn axis_inputs(axis: &str, value: i16) -> Vec<(String, bool)> {
+    let mut inputs = Vec::new();
+    if !axis.ends_with("trigger") {
+        inputs.push((format!("{}-", axis), value < -STICK_DEAD_ZONE));
+    }
+    inputs.push((format!("{}+", axis), value > STICK_DEAD_ZONE));
+    return inputs;
+}
+
+// Pointer 0 is the mouse
+fn finger(id: i64) -> u64 {
+    return (id as u64).wrapping_add(1);
+}
+
+// Held pad inputs are told apart by controller, so two players can hold the same button
+fn pad_name(id: u32, input: &str) -> String {
+    return format!("pad {} {}", id, input);
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, events: &mut Vec<InputEvent>) {
+        let sdl_events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in sdl_events {
+            // Keys in the keymap go to the keypad before they can be hotkeys
+            let handled = match &event {
+                Event::KeyDown { keycode, .. } if *keycode == Some(Keycode::Escape) => false,
+                Event::KeyDown { scancode: Some(scancode), .. } => self.keypad(scancode.name(), self.keymap.action(scancode.name()), true, events),
+                Event::KeyUp { scancode: Some(scancode), .. } => self.keypad(scancode.name(), self.keymap.action(scancode.name()), false, events),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    let input = button.string();
+                    self.keypad(&pad_name(*which, &input), self.keymap.pad_action(&input), true, events)
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    let input = button.string();
+                    self.keypad(&pad_name(*which, &input), self.keymap.pad_action(&input), false, events)
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    self.axis(*which, &axis.string(), *value, events);
+                    true
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.add_controller(*which);
+                    true
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.remove_controller(*which, events);
+                    true
+                },
+                _ => false,
+            };
+            if handled {
+                continue;
+            }
+
+            let event = match event {
+                Event::MouseButtonDown { which, mouse_btn: MouseButton::Left, x, y, .. } if which != TOUCH_MOUSE => {
+                    let (x, y) = self.mouse_position(x, y);
+                    InputEvent::PointerDown(0, x, y)
+                },
+                Event::MouseMotion { which, mousestate, x, y, .. } if which != TOUCH_MOUSE && mousestate.left() => {
+                    let (x, y) = self.mouse_position(x, y);
+                    InputEvent::PointerMotion(0, x, y)
+                },
+                Event::MouseButtonUp { which, mouse_btn: MouseButton::Left, .. } if which != TOUCH_MOUSE => InputEvent::PointerUp(0),
+                Event::FingerDown { finger_id, x, y, .. } => InputEvent::PointerDown(finger(finger_id), x, y),
+                Event::FingerMotion { finger_id, x, y, .. } => InputEvent::PointerMotion(finger(finger_id), x, y),
+                Event::FingerUp { finger_id, .. } => InputEvent::PointerUp(finger(finger_id)),
+                Event::Window { win_event: WindowEvent::SizeChanged(width, height), .. } => {
+                    self.window_size = (width as u32, height as u32);
+                    InputEvent::Redraw
+                },
+                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => InputEvent::Quit,
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => InputEvent::ToggleFullscreen,
+                Event::Window { win_event: WindowEvent::Exposed, .. } => InputEvent::Redraw,
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => InputEvent::NextUpscaler,
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => InputEvent::ToggleStats,
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => InputEvent::ToggleKeypad,
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => InputEvent::ToggleRecording,
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => InputEvent::SaveSettings,
+                // F12 saves what is on screen, Shift + F12 the display at its native resolution
+                Event::KeyDown { keycode: Some(Keycode::F12), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => InputEvent::NativeScreenshot,
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => InputEvent::Screenshot,
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => InputEvent::TogglePause,
+                Event::KeyDown { keycode: Some(Keycode::N), .. } => InputEvent::Step,
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => InputEvent::FastForward(true),
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => InputEvent::FastForward(false),
+                Event::KeyDown { keycode: Some(Keycode::Minus), .. } => InputEvent::Slower,
+                Event::KeyDown { keycode: Some(Keycode::Equals), .. } => InputEvent::Faster,
+                _ => continue,
+            };
+            events.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::keymap::{HOST_KEYS, PAD_INPUTS};
+    use sdl2::controller::{Axis, Button};
+    use sdl2::keyboard::Scancode;
+
+    // Every scancode SDL names has to be a key a keymap is allowed to use, and the
+    // other way round
+    #[test]
+    fn host_keys_cover_sdl() {
+        // The numbers SDL has scancodes for, from_i32 must not be given the ones between
+        let numbers = (4..=129).chain(133..=164).chain(176..=221).chain(224..=231).chain(257..=284);
+        let names: Vec<&str> = numbers.filter_map(Scancode::from_i32).map(Scancode::name).filter(|name| !name.is_empty()).collect();
+        assert!(names.len() > 200);
+        for name in &names {
+            assert!(HOST_KEYS.contains(name), "{} is not in HOST_KEYS", name);
+        }
+        for key in HOST_KEYS.iter() {
+            assert!(Scancode::from_name(key).is_some(), "SDL has no {} key", key);
+        }
+    }
+
+    // Every input SDL can send has to be one a keymap is allowed to use
+    #[test]
+    fn pad_inputs_cover_sdl() {
+        let buttons = [
+            Button::A, Button::B, Button::X, Button::Y, Button::Back, Button::Guide, Button::Start,
+            Button::LeftStick, Button::RightStick, Button::LeftShoulder, Button::RightShoulder,
+            Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+            Button::Misc1, Button::Paddle1, Button::Paddle2, Button::Paddle3, Button::Paddle4, Button::Touchpad,
+        ];
+        let axes = [Axis::LeftX, Axis::LeftY, Axis::RightX, Axis::RightY, Axis::TriggerLeft, Axis::TriggerRight];
+        let mut inputs: Vec<String> = buttons.iter().map(|button| button.string()).collect();
+        for axis in axes.iter() {
+            inputs.extend(axis_inputs(&axis.string(), 0).into_iter().map(|(input, _)| input));
+        }
+        for input in &inputs {
+            assert!(PAD_INPUTS.contains(&input.as_str()), "{} is not in PAD_INPUTS", input);
+        }
+        assert_eq!(inputs.len(), PAD_INPUTS.len());
     }
-    return ((1 << shift) as u16, shift);
-}
\ No newline at end of file
+}
@@ -0,0 +1,31 @@
+// The emulator and everything that does not depend on a frontend, the chip8
+// binary adds SDL and the terminal on top through the traits in frontend.rs.
+// Also built as a cdylib, which is the libretro core (see libretro.rs)
+pub mod assembler;
+pub mod beeper;
+pub mod cpu;
+pub mod database;
+pub mod disassembler;
+pub mod emulator;
+pub mod filter;
+pub mod framebuffer;
+pub mod frontend;
+pub mod keymap;
+pub mod libretro;
+pub mod movie;
+pub mod opcode;
+pub mod osd;
+pub mod palette;
+pub mod panel;
+pub mod phosphor;
+pub mod record;
+pub mod render;
+pub mod runner;
+pub mod savestate;
+pub mod screenshot;
+pub mod settings;
+pub mod speed;
+pub mod timing;
+pub mod turbo;
+pub mod upscale;
+pub mod wav;
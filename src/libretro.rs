@@ -0,0 +1,566 @@
+use super::beeper::{BeeperSettings, FrameAudio, RECORDING_SAMPLE_RATE};
+use super::cpu::{Quirks, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use super::emulator::{self, Emulator};
+use super::filter::Filter;
+use super::palette::Palette;
+use super::render::Renderer;
+use super::savestate;
+use super::timing::FRAME_RATE;
+use super::upscale::Upscaler;
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_uint};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+/*
+    The libretro API, so RetroArch (or any other libretro frontend) can load the
+    interpreter as a core. The cdylib build of the crate exports the retro_*
+    functions below; the frontend hands us the program, calls retro_run once per
+    60hz frame and gets the display back as XRGB8888 and the beeper as 16 bit
+    stereo. Only the parts of libretro.h we use are declared here
+*/
+
+pub const API_VERSION: c_uint = 1;
+
+pub const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+pub const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+pub const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+
+pub const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+pub const DEVICE_JOYPAD: c_uint = 1;
+pub const DEVICE_KEYBOARD: c_uint = 3;
+
+pub const DEVICE_ID_JOYPAD_B: c_uint = 0;
+pub const DEVICE_ID_JOYPAD_Y: c_uint = 1;
+pub const DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+pub const DEVICE_ID_JOYPAD_START: c_uint = 3;
+pub const DEVICE_ID_JOYPAD_UP: c_uint = 4;
+pub const DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+pub const DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+pub const DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+pub const DEVICE_ID_JOYPAD_A: c_uint = 8;
+pub const DEVICE_ID_JOYPAD_X: c_uint = 9;
+pub const DEVICE_ID_JOYPAD_L: c_uint = 10;
+pub const DEVICE_ID_JOYPAD_R: c_uint = 11;
+
+pub const MEMORY_SYSTEM_RAM: c_uint = 2;
+pub const REGION_NTSC: c_uint = 0;
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+// The frontend's callbacks, set before retro_init
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    chip8: Emulator,
+    renderer: Renderer,
+    audio: FrameAudio,
+    program: Vec<u8>,           // Kept for retro_reset
+    known: (u32, Quirks),       // The speed and quirks the program loaded with, for the auto options
+    video: Vec<u32>,            // The frame as XRGB8888
+    samples: Vec<i16>,          // The frame's sound as interleaved stereo
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+// Core options as "key", "Description; default|other values", null terminated for the frontend.
+// auto is what the database has for the program, or the defaults when it is not there
+const VARIABLES: [(&[u8], &[u8]); 4] = [
+    (b"chip8_ipf\0", b"Instructions per frame; auto|5|8|11|15|20|30|50|100|200|500|1000\0"),
+    (b"chip8_platform\0", b"Platform; auto|default|vip|modern|schip|xochip\0"),
+    (b"chip8_display_wait\0", b"Display wait quirk; auto|disabled|enabled\0"),
+    (b"chip8_palette\0", b"Palette; classic|amber|green|lcd|octo\0"),
+];
+
+// RetroPad buttons and the keys they press, for games that use 2 4 6 8 as arrows and 5 to fire
+const JOYPAD: [(c_uint, u8); 12] = [
+    (DEVICE_ID_JOYPAD_UP, 0x2),
+    (DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (DEVICE_ID_JOYPAD_A, 0x5),
+    (DEVICE_ID_JOYPAD_B, 0x0),
+    (DEVICE_ID_JOYPAD_X, 0x1),
+    (DEVICE_ID_JOYPAD_Y, 0x3),
+    (DEVICE_ID_JOYPAD_L, 0x7),
+    (DEVICE_ID_JOYPAD_R, 0x9),
+    (DEVICE_ID_JOYPAD_SELECT, 0xE),
+    (DEVICE_ID_JOYPAD_START, 0xF),
+];
+
+// The same keyboard layout as the window, libretro keycodes are lowercase ASCII for these
+const KEYBOARD: &[u8; 16] = b"1234qwerasdfzxcv";
+
+fn callbacks() -> Callbacks {
+    return *CALLBACKS.lock().unwrap();
+}
+
+fn variable(environment: EnvironmentFn, key: &[u8]) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr() as *const c_char, value: ptr::null() };
+    unsafe {
+        if !environment(ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) || variable.value.is_null() {
+            return None;
+        }
+        return CStr::from_ptr(variable.value).to_str().ok().map(String::from);
+    }
+}
+
+// Read the core options again, at load and whenever the frontend says they changed
+fn apply_variables(core: &mut Core, environment: EnvironmentFn) {
+    let (ipf, quirks) = core.known;
+    let value = variable(environment, VARIABLES[0].0);
+    core.chip8.instructions_per_frame = match value.and_then(|value| value.parse::<u32>().ok()) {
+        Some(ipf) => ipf.clamp(1, emulator::MAX_INSTRUCTIONS_PER_FRAME),
+        None => ipf,
+    };
+    // The platform sets every quirk, so display wait goes on top of it
+    let value = variable(environment, VARIABLES[1].0);
+    core.chip8.cpu.quirks = value.and_then(|value| Quirks::platform(&value).ok()).unwrap_or(quirks);
+    match variable(environment, VARIABLES[2].0).as_deref() {
+        Some("enabled") => core.chip8.cpu.quirks.display_wait = true,
+        Some("disabled") => core.chip8.cpu.quirks.display_wait = false,
+        _ => {},
+    }
+    if let Some(palette) = variable(environment, VARIABLES[3].0).and_then(|value| Palette::preset(&value)) {
+        core.renderer.palette = palette;
+        core.chip8.cpu.draw_flag = 1;
+    }
+}
+
+// The same random numbers every time a program starts, so runs (and netplay, which
+// needs every player's core to agree) can be repeated
+fn program_seed(program: &[u8]) -> u64 {
+    let digest = sha1_smol::Sha1::from(program).digest().bytes();
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&digest[..8]);
+    return u64::from_le_bytes(seed);
+}
+
+fn read_keyboard(input_state: InputStateFn) -> u16 {
+    let mut keyboard = 0;
+    unsafe {
+        for (button, key) in JOYPAD.iter() {
+            if input_state(0, DEVICE_JOYPAD, 0, *button) != 0 {
+                keyboard |= 1 << key;
+            }
+        }
+        for (key, code) in KEYBOARD.iter().enumerate() {
+            if input_state(0, DEVICE_KEYBOARD, 0, c_uint::from(*code)) != 0 {
+                keyboard |= 1 << key;
+            }
+        }
+    }
+    return keyboard;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    return API_VERSION;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+    let mut variables: Vec<Variable> = VARIABLES.iter()
+        .map(|(key, value)| Variable { key: key.as_ptr() as *const c_char, value: value.as_ptr() as *const c_char })
+        .collect();
+    variables.push(Variable { key: ptr::null(), value: ptr::null() });
+    unsafe {
+        callback(ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+// Everything goes through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// The core's name, version and the files it loads
+///
+/// # Safety
+///
+/// `info` must be null or point to a SystemInfo that can be written, and no other
+/// thread may use it during the call
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = SystemInfo {
+        library_name: b"Rust-Chip8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// The size of the display and the frame and sample rates
+///
+/// # Safety
+///
+/// `info` must be null or point to a SystemAvInfo that can be written, and no other
+/// thread may use it during the call
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+    let (width, height) = match &*CORE.lock().unwrap() {
+        Some(core) => core.chip8.cpu.resolution(),
+        None => (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+    };
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: width as c_uint,
+            base_height: height as c_uint,
+            max_width: width as c_uint,
+            max_height: height as c_uint,
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: SystemTiming {
+            fps: f64::from(FRAME_RATE),
+            sample_rate: f64::from(RECORDING_SAMPLE_RATE),
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = &mut *CORE.lock().unwrap() {
+        let quirks = core.chip8.cpu.quirks;
+        let mut chip8 = Emulator::new(core.chip8.instructions_per_frame);
+        chip8.cpu.quirks = quirks;
+        chip8.cpu.seed(program_seed(&core.program));
+        chip8.cpu.load_sprites();
+        if chip8.cpu.load_bytes(&core.program).is_ok() {
+            core.chip8 = chip8;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    if let Some(poll) = callbacks.input_poll {
+        unsafe { poll(); }
+    }
+    let mut core = CORE.lock().unwrap();
+    let core = match &mut *core {
+        Some(core) => core,
+        None => return,
+    };
+
+    if let Some(environment) = callbacks.environment {
+        let mut updated = false;
+        unsafe {
+            environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void);
+        }
+        if updated {
+            apply_variables(core, environment);
+        }
+    }
+    if let Some(input_state) = callbacks.input_state {
+        core.chip8.cpu.set_keyboard(read_keyboard(input_state));
+    }
+
+    core.chip8.run_frame();
+    core.renderer.update(&(core.chip8.cpu.display), core.chip8.cpu.resolution());
+
+    // The frontend wants a frame every time, even when the display did not change
+    let frame = core.renderer.render(&(core.chip8.cpu.display), core.chip8.cpu.resolution());
+    core.video.clear();
+    core.video.extend(frame.pixels.chunks(4).map(|pixel| u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]])));
+    core.chip8.cpu.draw_flag = 0;
+    if let Some(video_refresh) = callbacks.video_refresh {
+        unsafe {
+            video_refresh(core.video.as_ptr() as *const c_void, frame.width as c_uint, frame.height as c_uint, frame.width * 4);
+        }
+    }
+
+    core.samples.clear();
+    for sample in core.audio.next_frame(&core.chip8.tone()) {
+        let sample = (sample * f32::from(i16::MAX)) as i16;
+        core.samples.extend_from_slice(&[sample, sample]);
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        unsafe {
+            audio_sample_batch(core.samples.as_ptr(), core.samples.len() / 2);
+        }
+    }
+}
+
+/// Start the program in `game`, the bytes are copied so the frontend can free them after
+///
+/// # Safety
+///
+/// `game` must be null or point to a valid GameInfo, whose `data` is null or points to
+/// `size` readable bytes that are not written to during the call
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let program = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    let environment = match callbacks().environment {
+        Some(environment) => environment,
+        None => return false,
+    };
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+
+    let mut chip8 = match Emulator::load(&program) {
+        Ok((chip8, _)) => chip8,
+        Err(_) => return false,
+    };
+    chip8.cpu.seed(program_seed(&program));
+    let mut core = Core {
+        known: (chip8.instructions_per_frame, chip8.cpu.quirks),
+        chip8,
+        renderer: Renderer::new(Palette::default(), None, Filter::None, Upscaler::None, 1),
+        audio: FrameAudio::new(&BeeperSettings::default()),
+        program,
+        video: Vec::new(),
+        samples: Vec::new(),
+    };
+    apply_variables(&mut core, environment);
+    *CORE.lock().unwrap() = Some(core);
+    return true;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    return false;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    return REGION_NTSC;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    return savestate::STATE_SIZE;
+}
+
+/// Write a save state, retro_serialize_size bytes long
+///
+/// # Safety
+///
+/// `data` must be null or point to `size` bytes that can be written and that nothing
+/// else reads or writes during the call
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() || size < savestate::STATE_SIZE {
+        return false;
+    }
+    return match &*CORE.lock().unwrap() {
+        Some(core) => {
+            let state = savestate::save(&core.chip8);
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        },
+        None => false,
+    };
+}
+
+/// Load a save state written by retro_serialize
+///
+/// # Safety
+///
+/// `data` must be null or point to `size` readable bytes that are not written to
+/// during the call
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() || size < savestate::STATE_SIZE {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, savestate::STATE_SIZE);
+    return match &mut *CORE.lock().unwrap() {
+        Some(core) => savestate::load(&mut core.chip8, state).is_ok(),
+        None => false,
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+// The CHIP-8's 4K of memory, so frontends can show it or look for cheats
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    return match &mut *CORE.lock().unwrap() {
+        Some(core) => core.chip8.cpu.memory.as_mut_ptr() as *mut c_void,
+        None => ptr::null_mut(),
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    return match &*CORE.lock().unwrap() {
+        Some(core) => core.chip8.cpu.memory.len(),
+        None => 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        return match cmd {
+            ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == PIXEL_FORMAT_XRGB8888,
+            _ => false,
+        };
+    }
+
+    fn serialize() -> Vec<u8> {
+        let mut state = vec![0; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        return state;
+    }
+
+    // What a frontend does for save states: save, run on, load the state back and
+    // run the same frames again
+    #[test]
+    fn save_states_load_back_through_the_core() {
+        // 6005 A20A F033 D015 C0FF 1208, draws and keeps drawing random numbers
+        let program: [u8; 12] = [0x60, 0x05, 0xA2, 0x0A, 0xF0, 0x33, 0xD0, 0x15, 0xC0, 0xFF, 0x12, 0x04];
+        let game = GameInfo { path: ptr::null(), data: program.as_ptr() as *const c_void, size: program.len(), meta: ptr::null() };
+        retro_set_environment(environment);
+        assert!(unsafe { retro_load_game(&game) });
+        retro_run();
+
+        let state = serialize();
+        for _ in 0..5 {
+            retro_run();
+        }
+        let later = serialize();
+        assert_ne!(later, state);
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        for _ in 0..5 {
+            retro_run();
+        }
+        assert_eq!(serialize(), later);
+
+        // A state whose program counter is past the end of memory is turned down
+        let mut broken = state.clone();
+        broken[4 + 1 + 4096 + 16 + 2] = 0xFF;
+        broken[4 + 1 + 4096 + 16 + 3] = 0x0F;
+        assert!(!unsafe { retro_unserialize(broken.as_ptr() as *const c_void, broken.len()) });
+        assert_eq!(serialize(), later);
+        retro_unload_game();
+        assert!(!unsafe { retro_serialize(broken.as_mut_ptr() as *mut c_void, broken.len()) });
+    }
+}
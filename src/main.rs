@@ -1,110 +1,245 @@
-extern crate sdl2;
-mod cpu;
+mod headless;
+#[cfg(feature = "sdl2")]
 mod keys;
-mod opcode;
+mod options;
+#[cfg(feature = "sdl2")]
+mod screen;
+#[cfg(feature = "sdl2")]
 mod sound;
+#[cfg(unix)]
+mod terminal;
 
 use std::env;
-use sound::SoundSystem;
-use std::time::{Duration, Instant};
-use sdl2::rect::Rect;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use std::fs;
+use chip8::assembler;
+use chip8::disassembler;
+use chip8::emulator::Emulator;
+#[cfg(feature = "sdl2")]
+use chip8::frontend::{AudioSink, NullAudio};
+use chip8::movie::Movie;
+#[cfg(feature = "sdl2")]
+use chip8::panel::KeypadPanel;
+use chip8::phosphor::Phosphor;
+use chip8::render::Renderer;
+use chip8::runner::Runner;
+#[cfg(feature = "sdl2")]
+use chip8::runner;
+use chip8::settings;
+use chip8::turbo::InputLayer;
+#[cfg(feature = "sdl2")]
+use keys::SdlInput;
+use options::{Frontend, Mode, Options};
+#[cfg(feature = "sdl2")]
+use screen::Screen;
+#[cfg(feature = "sdl2")]
+use sound::{SoundError, SoundSystem};
 
 fn main() {
 
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("Not enough arguments! What game do you want to play!");
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\nRun chip8 help to see every option", e);
+            std::process::exit(2);
+        },
+    };
+    if let Err(e) = start(options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
-    if args.len() > 2 {
-        panic!("Too many arguments!");
+}
+
+fn start(options: Options) -> Result<(), String> {
+    match options.mode {
+        Mode::Help => {
+            print!("{}", options::USAGE);
+            return Ok(());
+        },
+        Mode::Assemble => return assemble(&options),
+        Mode::Disassemble => return disassemble(&options),
+        Mode::Info => return info(&options),
+        Mode::Play | Mode::Render => {},
     }
-    let game_name = &args[1];
-    let filename = format!("programs/{}", game_name);
 
-    let mut chip8: cpu::Cpu = cpu::Cpu::new();
-    chip8.load_program(&filename);
-    chip8.load_sprites();
-
-    let sdl_context = sdl2::init().unwrap();                        // SDL for graphics, sound and input
-    let video_subsystem = sdl_context.video().unwrap();             // Init Display
-    let mut sound_system = SoundSystem::initialize(&sdl_context);   // Init Sound System
-    let mut event_pump = sdl_context.event_pump().unwrap();         // Init Event System
+    let mut chip8 = Emulator::new(options.instructions_per_frame);
+    chip8.cpu.quirks = options.quirks;
+    if let Some(seed) = options.seed {
+        chip8.cpu.seed(seed);
+    }
+    chip8.cpu.load_program(&options.program)?;
+    chip8.cpu.load_sprites();
 
-    let window = video_subsystem.window("Rust-Chip8-Interpreter", 64*8, 32*8)
+    if options.mode == Mode::Render {
+        return headless::render(&options, chip8);
+    }
+    if options.headless {
+        return headless::run(&options, chip8);
+    }
+    if options.frontend == Frontend::Terminal {
+        return play_in_terminal(&options, chip8);
+    }
+    return play(options, chip8);
+}
+
+fn read_program(path: &str) -> Result<Vec<u8>, String> {
+    return fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e));
+}
+
+fn disassemble(options: &Options) -> Result<(), String> {
+    let listing = disassembler::disassemble(&read_program(&options.program)?);
+    return match &options.output {
+        Some(path) => fs::write(path, listing).map_err(|e| format!("Could not write {}: {}", path, e)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        },
+    };
+}
+
+fn assemble(options: &Options) -> Result<(), String> {
+    let source = fs::read_to_string(&options.program).map_err(|e| format!("Could not read {}: {}", options.program, e))?;
+    let program = assembler::assemble(&source)?;
+    let path = options.output.as_ref().ok_or("assemble needs an output file")?;
+    fs::write(path, &program).map_err(|e| format!("Could not write {}: {}", path, e))?;
+    println!("Wrote {} bytes to {}", program.len(), path);
+    return Ok(());
+}
+
+fn info(options: &Options) -> Result<(), String> {
+    let program = read_program(&options.program)?;
+    println!("{}", options.program);
+    if let Some(title) = &options.title {
+        println!("    {}", title);
+    }
+    println!("    SHA-1: {}", settings::sha1(&program));
+    for line in disassembler::describe(&program) {
+        println!("    {}", line);
+    }
+    if let Some(path) = settings::rom_file(&program).filter(|path| path.exists()) {
+        println!("    Settings: {}", path.display());
+    }
+    println!("    Quirks: {}", options.quirks.describe());
+    println!("    Instructions per frame: {}", options.instructions_per_frame);
+    return Ok(());
+}
+
+// The run loop with the options every frontend shares
+fn runner(options: &Options, chip8: Emulator, renderer: Renderer) -> Runner {
+    let mut runner = Runner::new(chip8, renderer, &options.program);
+    runner.speed.fast_forward_rate = options.fast_forward_rate;
+    runner.record_format = options.record_format;
+    runner.beeper = options.beeper.clone();
+    runner.movie = options.record_movie.as_ref().map(|_| Movie::new(&runner.chip8));
+    runner.input = InputLayer::new(options.keymap.turbos.clone(), options.keymap.macros.clone());
+    runner.settings_file = options.settings_file.clone();
+    if let Some(title) = &options.title {
+        runner.title = title.clone();
+    }
+    return runner;
+}
+
+fn save_movie(options: &Options, movie: Option<Movie>) -> Result<(), String> {
+    return match (movie, &options.record_movie) {
+        (Some(movie), Some(path)) => movie.save(path),
+        _ => Ok(()),
+    };
+}
+
+#[cfg(unix)]
+fn play_in_terminal(options: &Options, chip8: Emulator) -> Result<(), String> {
+    let (mut video, mut audio, mut input) = terminal::open(options)?;
+
+    // No filters or plain upscaling, every pixel is already a character or less
+    let phosphor = options.phosphor.map(Phosphor::new);
+    let renderer = Renderer::new(options.palette.clone(), phosphor, chip8::filter::Filter::None, options.upscaler, 1);
+    let mut runner = runner(options, chip8, renderer);
+    runner.run(&mut video, &mut audio, &mut input)?;
+    return save_movie(options, runner.movie);
+}
+
+#[cfg(not(unix))]
+fn play_in_terminal(_options: &Options, _chip8: Emulator) -> Result<(), String> {
+    return Err(String::from("The terminal frontend is only available on Linux and macOS"));
+}
+
+#[cfg(feature = "sdl2")]
+fn play(options: Options, chip8: Emulator) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;                                // SDL for graphics, sound and input
+    let video_subsystem = sdl_context.video()?;                     // Init Display
+    let mut sound_system = open_audio(&sdl_context, &options)?;     // Init Sound System
+    let controllers = sdl_context.game_controller()                 // Init Game Controllers, optional
+        .map_err(|e| eprintln!("Warning: no game controllers, {}", e))
+        .ok();
+
+    let (mut width, height) = chip8.cpu.resolution();
+    if options.touch_keypad {
+        width = KeypadPanel::size(width, height).0;
+    }
+    let window_size = (width as u32 * options.scale, height as u32 * options.scale);
+    let mut input = SdlInput::new(sdl_context.event_pump()?, options.keymap.clone(), controllers, window_size);   // Init Event System
+    let window = video_subsystem.window(options.title.as_deref().unwrap_or(runner::TITLE), window_size.0, window_size.1)
         .position_centered()
+        .resizable()
         .build()
-        .unwrap();
+        .map_err(|e| format!("Could not open a window: {}", e))?;
 
-    let mut canvas = window                                        // Canvas is the renderer
+    let canvas = window                                             // Canvas is the renderer
         .into_canvas()
         .accelerated()
         .build()
-        .unwrap();     
+        .map_err(|e| format!("Could not draw in the window: {}", e))?;
 
     let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_streaming(PixelFormatEnum::RGB332, 64, 32)
-        .map_err(|e| e.to_string()).unwrap();
-
-    sound_system.device.resume();
-    chip8.last_time = Instant::now();
-    let mut oparray: [u8; 2];
-
-    'running: loop {            // Main loop to run the program and execute instructions
-
-        oparray = [
-                chip8.memory[usize::from(chip8.program_counter)],
-                chip8.memory[usize::from(chip8.program_counter) + 1]
-        ];
-
-        // Program counter is incremented here because if we are waiting for a keypress 
-        // we dont want to incremement it prematurely or we will end eup skipping instructions
-        // In addition it must be before execute as certain instruction modify where the PC is and
-        // they modify it to where they want it rather than the instruction before what they want
-        if !chip8.waiting_for_key_flag {        // If we arent waiting for a keyboard event, 
-            chip8.program_counter += 2;
-            chip8.execute(&oparray);            // we can just execute the next opcode
-        }
-
-        // Handle input events
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'running      // Specifies which loop to break from
-                },
-                Event::KeyDown {keycode: Some(x), .. } => {            // On keydown, set keyboard to 1 in that digit
-                    let (new_key, shift) = keys::handle_key_event(&x);
-                    chip8.keyboard |= new_key;
-
-                    // If we were waiting for a key event due to Fx0A instruction
-                    // We need to finish off the instruction by placing the input key to register[x]
-                    if chip8.waiting_for_key_flag {                      
-                        chip8.key_pressed(shift);
-                    } 
-                },
-                Event::KeyUp {keycode: Some(x), .. } => {              // On keyup, set keyboard to 0 in that digit
-                    let (new_key, _shift) = keys::handle_key_event(&x);
-                    chip8.keyboard &= !new_key;
-                },
-                _ => {}
-            }
-        }
-
-        // Play or Pause sound?
-        sound_system.handle_timer(&(chip8.sound_timer));
+    let mut screen = Screen::new(canvas, &creator, options.scaling);
+    let phosphor = options.phosphor.map(Phosphor::new);
+    let renderer = Renderer::new(options.palette.clone(), phosphor, options.filter, options.upscaler, options.scale as usize);
 
-        // Update the display if needed
-        if chip8.draw_flag == 1 {
-            canvas.clear();                                                             // Clear the buffer
-            texture.update(None, &(chip8.display), 64).unwrap();                        // Update texture
-            canvas.copy(&texture, None, Some(Rect::new(0, 0, 64*8, 32*8))).unwrap();    // Update canvas
-            canvas.present();                                                           // Display canvas
-            chip8.draw_flag = 0;
+    let mut runner = runner(&options, chip8, renderer);
+    if options.touch_keypad {
+        runner.panel = Some(KeypadPanel::new());
+    }
+    let mut null_audio = NullAudio;
+    let audio: &mut dyn AudioSink = match &mut sound_system {
+        Some(sound_system) => sound_system,
+        None => &mut null_audio,
+    };
+    if let Err(e) = runner.run(&mut screen, audio, &mut input) {
+        eprintln!("Recording failed: {}", e);
+    }
+    if let Some(Err(e)) = sound_system.map(SoundSystem::finish) {
+        eprintln!("Audio capture failed: {}", e);
+    }
+    if let Err(e) = save_movie(&options, runner.movie) {
+        eprintln!("{}", e);
+    }
+    return Ok(());
+}
+
+#[cfg(not(feature = "sdl2"))]
+fn play(_options: Options, _chip8: Emulator) -> Result<(), String> {
+    return Err(String::from("This chip8 was built without SDL, play with --frontend terminal or run --headless"));
+}
+
+// Play without sound rather than not at all when there is no audio device, but
+// stop when the capture file can not be created
+#[cfg(feature = "sdl2")]
+fn open_audio(sdl_context: &sdl2::Sdl, options: &Options) -> Result<Option<SoundSystem>, String> {
+    if !options.audio {
+        if options.capture_audio.is_some() {
+            eprintln!("Warning: --capture-audio does nothing with --no-audio, render to a .wav instead");
         }
-        
-        std::thread::sleep(Duration::new(0, 90000)); // previously 500
+        return Ok(None);
     }
-}
\ No newline at end of file
+    return match SoundSystem::initialize(sdl_context, &options.beeper, options.audio_device.as_deref(), options.capture_audio.as_deref()) {
+        Ok(sound_system) => Ok(Some(sound_system)),
+        Err(SoundError::Device(e)) => {
+            match &options.capture_audio {
+                Some(path) => eprintln!("Warning: no sound, {}. Nothing is captured to {}", e, path),
+                None => eprintln!("Warning: no sound, {}", e),
+            }
+            Ok(None)
+        },
+        Err(SoundError::Capture(e)) => Err(e),
+    };
+}
@@ -0,0 +1,244 @@
+use super::cpu::{Quirks, QUIRKS};
+use super::emulator::Emulator;
+use super::frontend::{InputEvent, InputSource};
+use std::fs;
+use std::mem;
+
+const HEADER: &str = "chip8-movie 2";
+const OLD_HEADER: &str = "chip8-movie 1";        // Only the keyboard at the start of every frame
+
+/*
+    A recording of the keyboard for every frame, so a run can be played back
+    exactly. Saved as text, a header line, the random number seed, instructions
+    per frame and quirks the run started with, then one line per frame with the
+    keyboard bits in hex. A frame has every keyboard the Cpu was given since the
+    frame before, in order, so a key pressed and let go between two frames
+    (which FX0A still sees) is kept. The last one is what the frame ran with, e.g.
+        chip8-movie 2
+        seed 1234
+        ipf 11
+        quirks shift,load_store,clip
+        0000
+        0020 0000
+*/
+#[derive(Default)]
+pub struct Movie {
+    pub seed: Option<u64>,
+    pub ipf: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub frames: Vec<Vec<u16>>,
+    pending: Vec<u16>,                  // Keyboards given to the Cpu since the last frame
+}
+
+impl Movie {
+    // Start recording a run from where the emulator is now
+    pub fn new(chip8: &Emulator) -> Movie {
+        return Movie {
+            seed: Some(chip8.cpu.rng.state),
+            ipf: Some(chip8.instructions_per_frame),
+            quirks: Some(chip8.cpu.quirks),
+            ..Movie::default()
+        };
+    }
+
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read movie {}: {}", path, e))?;
+        return Movie::parse(&text).map_err(|e| format!("{}: {}", path, e));
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate().peekable();
+        let old = match lines.next() {
+            Some((_, HEADER)) => false,
+            Some((_, OLD_HEADER)) => true,
+            _ => return Err(format!("Not a movie, it should start with '{}'", HEADER)),
+        };
+        let mut movie = Movie::default();
+        while let Some((number, line)) = lines.next_if(|(_, line)| !old && line.starts_with(|c: char| c.is_ascii_alphabetic() && !c.is_ascii_hexdigit())) {
+            let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            match name {
+                "seed" => movie.seed = Some(value.parse().map_err(|_| format!("line {}: '{}' is not a seed", number + 1, value))?),
+                "ipf" => movie.ipf = Some(value.parse().map_err(|_| format!("line {}: '{}' is not a number", number + 1, value))?),
+                "quirks" => movie.quirks = Some(quirk_list(value).map_err(|e| format!("line {}: {}", number + 1, e))?),
+                _ => return Err(format!("line {}: '{}' is not a movie setting", number + 1, name)),
+            }
+        }
+        for (number, line) in lines {
+            let frame = line.split_whitespace()
+                .map(|keyboard| u16::from_str_radix(keyboard, 16))
+                .collect::<Result<Vec<u16>, _>>()
+                .ok()
+                .filter(|frame| !frame.is_empty())
+                .ok_or_else(|| format!("line {}: '{}' is not a keyboard value", number + 1, line))?;
+            movie.frames.push(frame);
+        }
+        return Ok(movie);
+    }
+
+    pub fn save(self: &Self, path: &str) -> Result<(), String> {
+        return fs::write(path, self.to_text()).map_err(|e| format!("Could not write movie {}: {}", path, e));
+    }
+
+    pub fn to_text(self: &Self) -> String {
+        let mut text = String::from(HEADER);
+        text.push('\n');
+        if let Some(seed) = self.seed {
+            text.push_str(&format!("seed {}\n", seed));
+        }
+        if let Some(ipf) = self.ipf {
+            text.push_str(&format!("ipf {}\n", ipf));
+        }
+        if let Some(quirks) = &self.quirks {
+            let list = quirks.describe();
+            text.push_str(&format!("quirks {}\n", if list.is_empty() { "none" } else { &list }));
+        }
+        for frame in &self.frames {
+            let keyboards: Vec<String> = frame.iter().map(|keyboard| format!("{:04X}", keyboard)).collect();
+            text.push_str(&keyboards.join(" "));
+            text.push('\n');
+        }
+        return text;
+    }
+
+    // Set the emulator up the way the recorded run started
+    pub fn apply(self: &Self, chip8: &mut Emulator) {
+        if let Some(seed) = self.seed {
+            chip8.cpu.seed(seed);
+        }
+        if let Some(ipf) = self.ipf {
+            chip8.instructions_per_frame = ipf;
+        }
+        if let Some(quirks) = self.quirks {
+            chip8.cpu.quirks = quirks;
+        }
+    }
+
+    // A keyboard the Cpu was given
+    pub fn record(self: &mut Self, keyboard: u16) {
+        if self.pending.last() != Some(&keyboard) {
+            self.pending.push(keyboard);
+        }
+    }
+
+    // A frame is about to run with the last keyboard recorded
+    pub fn end_frame(self: &mut Self, keyboard: u16) {
+        self.record(keyboard);
+        self.frames.push(mem::take(&mut self.pending));
+    }
+}
+
+// The quirks named in the list on and every other one off
+fn quirk_list(list: &str) -> Result<Quirks, String> {
+    let names: Vec<&str> = list.split(',').map(str::trim).filter(|name| !name.is_empty() && *name != "none").collect();
+    let mut quirks = Quirks::default();
+    for quirk in QUIRKS.iter() {
+        quirks.set(quirk, names.contains(quirk))?;
+    }
+    if let Some(unknown) = names.iter().find(|name| !QUIRKS.contains(name)) {
+        return Err(format!("Quirk '{}' must be one of {}", unknown, QUIRKS.join(", ")));
+    }
+    return Ok(quirks);
+}
+
+// Plays a movie back as input, one frame of keyboards every time it is polled. The run
+// loop polls once per pass, so it only lines up while every pass runs a single frame
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    keyboard: u16,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        return MoviePlayer { movie, frame: 0, keyboard: 0 };
+    }
+}
+
+impl InputSource for MoviePlayer {
+    fn poll(&mut self, events: &mut Vec<InputEvent>) {
+        // Once the movie runs out no keys are held
+        let frame = self.movie.frames.get(self.frame).cloned().unwrap_or_else(|| vec![0]);
+        for keyboard in frame {
+            let changed = keyboard ^ self.keyboard;
+            for key in 0..16 {
+                if (changed >> key) & 0x01 == 1 {
+                    events.push(if (keyboard >> key) & 0x01 == 1 { InputEvent::KeyDown(key) } else { InputEvent::KeyUp(key) });
+                }
+            }
+            self.keyboard = keyboard;
+        }
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::frontend::{CapturedAudio, NullVideo, ScriptedInput};
+    use crate::palette::Palette;
+    use crate::render::Renderer;
+    use crate::runner::Runner;
+    use crate::upscale::Upscaler;
+
+    // LD V0, K then RND V1, #FF and loop
+    const PROGRAM: [u8; 6] = [0xF0, 0x0A, 0xC1, 0xFF, 0x12, 0x04];
+
+    fn runner(seed: u64) -> Runner {
+        let mut chip8 = Emulator::new(11);
+        chip8.cpu.seed(seed);
+        chip8.cpu.load_bytes(&PROGRAM).unwrap();
+        let renderer = Renderer::new(Palette::default(), None, Filter::None, Upscaler::None, 1);
+        let mut runner = Runner::new(chip8, renderer, "test.ch8");
+        runner.throttle = false;
+        return runner;
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = "chip8-movie 2\nseed 99\nipf 30\nquirks jump,clip\n0000\n0020 0000\n";
+        let movie = Movie::parse(text).unwrap();
+        assert_eq!(movie.seed, Some(99));
+        assert_eq!(movie.ipf, Some(30));
+        assert_eq!(movie.quirks.unwrap().describe(), "jump,clip");
+        assert_eq!(movie.frames, vec![vec![0x0000], vec![0x0020, 0x0000]]);
+        assert_eq!(movie.to_text(), text);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let movie = Movie::parse("chip8-movie 1\n0000\n0020\n").unwrap();
+        assert_eq!(movie.seed, None);
+        assert_eq!(movie.frames, vec![vec![0x0000], vec![0x0020]]);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(Movie::parse("chip8-movie 2\nspeed 3\n").is_err());
+        assert!(Movie::parse("chip8-movie 2\n00G0\n").is_err());
+        assert!(Movie::parse("not a movie\n").is_err());
+    }
+
+    // A key pressed and let go before the next frame still answers FX0A, and the
+    // random number comes out the same from a runner seeded differently
+    #[test]
+    fn plays_back_a_run() {
+        let mut recording = runner(42);
+        recording.movie = Some(Movie::new(&recording.chip8));
+        recording.frame_limit = Some(4);
+        let mut input = ScriptedInput::new(vec![vec![], vec![InputEvent::KeyDown(5), InputEvent::KeyUp(5)], vec![], vec![]]);
+        recording.run(&mut NullVideo, &mut CapturedAudio::default(), &mut input).unwrap();
+        assert_eq!(recording.chip8.cpu.registers[0], 5);
+        let text = recording.movie.take().unwrap().to_text();
+
+        let movie = Movie::parse(&text).unwrap();
+        let mut playback = runner(7);
+        movie.apply(&mut playback.chip8);
+        playback.frame_limit = Some(movie.frames.len() as u64);
+        playback.run(&mut NullVideo, &mut CapturedAudio::default(), &mut MoviePlayer::new(movie)).unwrap();
+        assert_eq!(playback.chip8.cpu.registers[0], 5);
+        assert_eq!(playback.chip8.cpu.registers[1], recording.chip8.cpu.registers[1]);
+        assert_eq!(playback.chip8.cpu.keyboard, 0);
+    }
+}
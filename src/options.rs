@@ -0,0 +1,424 @@
+use chip8::beeper::{BeeperSettings, Waveform};
+use chip8::cpu::Quirks;
+use chip8::database::{self, Entry};
+use chip8::emulator;
+use chip8::filter::Filter;
+use chip8::keymap::{KeyBindings, Keymap};
+use chip8::palette::Palette;
+use chip8::record::Format;
+use chip8::settings::Settings;
+use chip8::upscale::Upscaler;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage:
+    chip8 [run] <rom> [options]           Play a program
+    chip8 render <rom> -o FILE [options]  Run without a window and record the frames to a file
+    chip8 disasm <rom> [-o FILE]          Print the program as assembly
+    chip8 assemble <source> -o FILE       Turn assembly back into a program
+    chip8 info <rom>                      Show what is worth knowing about a program
+    chip8 help                            Show this
+
+The rom is a path, or the name of a file in programs/
+
+Machine:
+    --platform NAME         default, vip, modern, schip or xochip, the quirks games were written for
+    --quirks LIST           Turn quirks on or off on top of the platform, e.g. \"jump,-clip\"
+                            (shift, load_store, jump, vf_reset, clip, display_wait)
+    --display-wait          The same as --quirks display_wait, drawing a sprite waits for the next frame
+    --ipf N                 Instructions per frame, 11 by default
+    --seed N                Make the random numbers the same every run
+
+Display:
+    --scale N               Window size as a multiple of the display, 8 by default
+    --scaling integer|fit   Only scale by whole numbers, or fill the window
+    --palette NAME|COLORS   classic, amber, green, lcd, octo or hex colors like \"#000000,#33FF33\"
+    --phosphor DECAY        Fade erased pixels, keeping DECAY (0 to 1) of the brightness each frame
+    --filter NAME           scanlines, shadow-mask, bloom or lcd
+    --upscaler NAME         epx, scale2x, scale3x, scale4x or xbr (xBR-like)
+    --frontend sdl|terminal Play in a window or in the terminal
+    --charset half|braille  The characters the terminal draws with
+    --headless              Run for --frames frames without a window or sound and print the display
+
+Sound:
+    --no-audio              Never open an audio device
+    --audio-device NAME     Play on a device other than the default one
+    --waveform NAME|FILE    square, triangle, sine or a .wav file with one cycle
+    --pitch HZ              330 by default
+    --volume PERCENT        10 by default
+    --min-beep MS           The shortest a beep can be, 35 by default
+    --capture-audio FILE    Save everything the audio device plays to a .wav
+
+Input:
+    --keymap NAME|FILE      hex, vip, numpad or a .toml keymap file
+    --touch-keypad          A keypad to click or touch next to the display
+    --key-hold MS           How long a key stays held in the terminal, 250 by default
+    --fast-forward N        Frames per frame while Tab is held, 0 (the default) is unlimited
+
+Recording:
+    --record-format NAME    gif, apng, y4m or wav, for F10
+                            (the videos get their sound in a .wav next to them)
+    --record-movie FILE     Save the keyboard for every frame
+    --movie FILE            Play the keyboard back from a movie (render and --headless)
+    --frames N              How many frames to run (render and --headless)
+    -o, --output FILE       Where render, disasm and assemble write to
+";
+
+#[derive(PartialEq)]
+pub enum Mode {
+    Play,           // Open a window and play the program
+    Render,         // Run without a window and record the frames to a file
+    Disassemble,    // Print the program as assembly
+    Assemble,       // Turn assembly into a program
+    Info,           // Describe the program
+    Help,
+}
+
+// Window size as a multiple of the display
+pub const DEFAULT_SCALE: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scaling {
+    Integer,        // Only whole multiples of the display size, sharpest pixels
+    Fit,            // As large as fits in the window while keeping the aspect ratio
+}
+
+impl Scaling {
+    pub fn parse(value: &str) -> Result<Scaling, String> {
+        return match value {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!("Scaling '{}' must be integer or fit", value)),
+        };
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Frontend {
+    Sdl,
+    Terminal,   // Draws with text in the terminal, for SSH sessions and machines without a display
+}
+
+#[derive(Clone, Copy)]
+pub enum Charset {
+    HalfBlock,  // Two pixels per character
+    Braille,    // Eight pixels per character, for small terminals
+}
+
+// Command line options, see USAGE. Some are only read by the window
+#[cfg_attr(not(feature = "sdl2"), allow(dead_code))]
+pub struct Options {
+    pub mode: Mode,
+    pub program: String,            // The rom, or the source file to assemble
+    pub instructions_per_frame: u32,
+    pub fast_forward_rate: u32,     // Frames per frame while Tab is held, 0 means unlimited
+    pub palette: Palette,
+    pub scale: u32,                 // Starting window size as a multiple of the display
+    pub scaling: Scaling,
+    pub phosphor: Option<f32>,      // Fade out erased pixels instead of turning them off straight away
+    pub quirks: Quirks,
+    pub filter: Filter,
+    pub upscaler: Upscaler,
+    pub record_format: Format,      // Format used when recording with F10
+    pub record_movie: Option<String>,
+    pub movie: Option<String>,      // Keyboard input to play back when rendering
+    pub frames: Option<u64>,        // How many frames to render, defaults to the movie's length
+    pub output: Option<String>,
+    pub frontend: Frontend,
+    pub charset: Charset,
+    pub key_hold: u64,              // Milliseconds a key stays held in the terminal without being sent again
+    pub beeper: BeeperSettings,
+    pub capture_audio: Option<String>,  // Save everything the audio device plays
+    pub audio: bool,                // False to never open an audio device
+    pub audio_device: Option<String>,   // The default device when not set
+    pub keymap: Keymap,             // With the overrides for the program already applied
+    pub touch_keypad: bool,         // Show a keypad to click or touch next to the display
+    pub seed: Option<u64>,          // Seed for the random numbers, random when not set
+    pub headless: bool,             // Run without a window or sound and print the display at the end
+    pub settings_file: Option<PathBuf>, // Where F5 saves the program's own settings
+    pub title: Option<String>,      // The program's title and authors from the database
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut mode = Mode::Play;
+        let mut program = None;
+        let mut instructions_per_frame = emulator::DEFAULT_INSTRUCTIONS_PER_FRAME;
+        let mut fast_forward_rate = 0;
+        let mut palette = Palette::default();
+        let mut scale = DEFAULT_SCALE;
+        let mut scaling = Scaling::Fit;
+        let mut phosphor = None;
+        let mut platform = None;
+        let mut quirks = Vec::new();
+        let mut filter = Filter::None;
+        let mut upscaler = Upscaler::None;
+        let mut record_format = Format::Gif;
+        let mut record_movie = None;
+        let mut movie = None;
+        let mut frames = None;
+        let mut output = None;
+        let mut frontend = if cfg!(feature = "sdl2") { Frontend::Sdl } else { Frontend::Terminal };
+        let mut charset = Charset::HalfBlock;
+        let mut key_hold = 250;
+        let mut beeper = BeeperSettings::default();
+        let mut capture_audio = None;
+        let mut audio = true;
+        let mut audio_device = None;
+        let mut keymap = None;
+        let mut touch_keypad = false;
+        let mut seed = None;
+        let mut headless = false;
+
+        let mut iter = args.iter().skip(1).peekable();
+        let subcommand = match iter.peek().map(|arg| arg.as_str()) {
+            Some("run") => Some(Mode::Play),
+            Some("render") => Some(Mode::Render),
+            Some("disasm") => Some(Mode::Disassemble),
+            Some("assemble") => Some(Mode::Assemble),
+            Some("info") => Some(Mode::Info),
+            Some("help") | Some("--help") | Some("-h") | None => Some(Mode::Help),
+            _ => None,
+        };
+        if let Some(subcommand) = subcommand {
+            mode = subcommand;
+            iter.next();
+        }
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--ipf" => instructions_per_frame = parse_number(arg, iter.next())?.clamp(1, emulator::MAX_INSTRUCTIONS_PER_FRAME),
+                "--fast-forward" => fast_forward_rate = parse_number(arg, iter.next())?,
+                "--palette" => palette = Palette::parse(parse_value(arg, iter.next())?)?,
+                "--scale" => scale = parse_number(arg, iter.next())?.max(1),
+                "--scaling" => scaling = Scaling::parse(parse_value(arg, iter.next())?)?,
+                "--phosphor" => phosphor = Some(parse_fraction(arg, iter.next())?),
+                "--platform" => platform = Some(parse_value(arg, iter.next())?.to_string()),
+                "--quirks" => quirks.push(parse_value(arg, iter.next())?.to_string()),
+                "--display-wait" => quirks.push(String::from("display_wait")),
+                "--seed" => seed = Some(parse_value(arg, iter.next())?.parse::<u64>().map_err(|_| format!("{} must be a number", arg))?),
+                "--headless" => headless = true,
+                "--filter" => filter = Filter::parse(parse_value(arg, iter.next())?)?,
+                "--upscaler" => upscaler = Upscaler::parse(parse_value(arg, iter.next())?)?,
+                "--record-format" => record_format = Format::parse(parse_value(arg, iter.next())?)?,
+                "--record-movie" => record_movie = Some(parse_value(arg, iter.next())?.to_string()),
+                "--movie" => movie = Some(parse_value(arg, iter.next())?.to_string()),
+                "--frames" => frames = Some(u64::from(parse_number(arg, iter.next())?)),
+                "-o" | "--output" => output = Some(parse_value(arg, iter.next())?.to_string()),
+                "--frontend" => frontend = match parse_value(arg, iter.next())? {
+                    "sdl" => Frontend::Sdl,
+                    "terminal" => Frontend::Terminal,
+                    _ => return Err(format!("{} must be sdl or terminal", arg)),
+                },
+                "--charset" => charset = match parse_value(arg, iter.next())? {
+                    "half" => Charset::HalfBlock,
+                    "braille" => Charset::Braille,
+                    _ => return Err(format!("{} must be half or braille", arg)),
+                },
+                "--key-hold" => key_hold = u64::from(parse_number(arg, iter.next())?),
+                "--waveform" => beeper.waveform = Waveform::parse(parse_value(arg, iter.next())?)?,
+                "--pitch" => beeper.pitch = parse_number(arg, iter.next())?.max(1) as f32,
+                "--volume" => beeper.volume = parse_number(arg, iter.next())?.min(100) as f32 / 100.0,
+                "--capture-audio" => capture_audio = Some(parse_value(arg, iter.next())?.to_string()),
+                "--no-audio" => audio = false,
+                "--audio-device" => audio_device = Some(parse_value(arg, iter.next())?.to_string()),
+                "--keymap" => keymap = Some(parse_value(arg, iter.next())?.to_string()),
+                "--touch-keypad" => touch_keypad = true,
+                "--min-beep" => beeper.minimum_beep = parse_number(arg, iter.next())? as f32 / 1000.0,
+                "-h" | "--help" => mode = Mode::Help,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                _ if program.is_some() => return Err(format!("Only one program can be given, '{}' is one too many", arg)),
+                _ => program = Some(arg.clone()),
+            }
+        }
+
+        let program = match (program, &mode) {
+            (_, Mode::Help) => String::new(),
+            (Some(program), Mode::Assemble) => program,
+            (Some(program), _) => find_program(&program)?,
+            (None, Mode::Assemble) => return Err(String::from("What should be assembled? Give the source file")),
+            (None, _) => return Err(String::from("What game do you want to play? Give the path to a rom")),
+        };
+        if mode == Mode::Assemble && output.is_none() {
+            return Err(String::from("assemble needs a file to write the program to, e.g. -o game.ch8"));
+        }
+        if headless && frames.is_none() && movie.is_none() {
+            return Err(String::from("--headless needs --frames N or a --movie to know when to stop"));
+        }
+
+        // The saved settings fill in whatever the command line did not set
+        let (settings, settings_file, entry) = match mode {
+            Mode::Play | Mode::Render | Mode::Info => {
+                let bytes = fs::read(&program).map_err(|e| format!("Could not read {}: {}", program, e))?;
+                // The database only adds to what is known, so a broken one is not a reason to stop
+                let entry = database::lookup(&bytes).unwrap_or_else(|e| {
+                    eprintln!("Warning: {}, running without the database", e);
+                    None
+                });
+                let (settings, settings_file) = Settings::for_program(&bytes, entry.as_ref().map(Entry::settings))?;
+                (settings, settings_file, entry)
+            },
+            _ => (Settings::default(), None, None),
+        };
+        let unset = |option: &str| !args.iter().any(|arg| arg == option);
+        if let (Some(ipf), true) = (settings.ipf, unset("--ipf")) {
+            instructions_per_frame = ipf.clamp(1, emulator::MAX_INSTRUCTIONS_PER_FRAME);
+        }
+        if let (Some(value), true) = (settings.scale, unset("--scale")) {
+            scale = value.max(1);
+        }
+        if let (Some(value), true) = (&settings.scaling, unset("--scaling")) {
+            scaling = Scaling::parse(value)?;
+        }
+        if let (Some(value), true) = (&settings.palette, unset("--palette")) {
+            palette = Palette::parse(value)?;
+        }
+        if let (Some(value), true) = (settings.phosphor, unset("--phosphor")) {
+            phosphor = Some(value);
+        }
+        if let (Some(value), true) = (&settings.filter, unset("--filter")) {
+            filter = Filter::parse(value)?;
+        }
+        if let (Some(value), true) = (&settings.upscaler, unset("--upscaler")) {
+            upscaler = Upscaler::parse(value)?;
+        }
+        if let (Some(value), true) = (settings.volume, unset("--volume")) {
+            beeper.volume = value.min(100) as f32 / 100.0;
+        }
+        let machine = machine(platform.as_deref(), &quirks, &settings)?;
+        let keymap = match keymap {
+            Some(keymap) => Keymap::parse(&keymap, &program)?,
+            None => {
+                let mut base = match &settings.keymap {
+                    Some(keymap) => Keymap::parse(keymap, &program)?,
+                    None => Keymap::default(),
+                };
+                if let Some(entry) = &entry {
+                    entry.add_keys(&mut base);
+                }
+                let bindings = KeyBindings { keys: settings.keys, pad: settings.pad, ..KeyBindings::default() };
+                bindings.build(Some(base))?
+            },
+        };
+
+        return Ok(Options {
+            mode,
+            program,
+            instructions_per_frame,
+            fast_forward_rate,
+            palette,
+            scale,
+            scaling,
+            phosphor,
+            quirks: machine,
+            filter,
+            upscaler,
+            record_format,
+            record_movie,
+            movie,
+            frames,
+            output,
+            frontend,
+            charset,
+            key_hold,
+            beeper,
+            capture_audio,
+            audio,
+            audio_device,
+            keymap,
+            touch_keypad,
+            seed,
+            headless,
+            settings_file,
+            title: entry.map(|entry| match entry.authors.is_empty() {
+                true => entry.title,
+                false => format!("{} by {}", entry.title, entry.authors.join(", ")),
+            }),
+        });
+    }
+}
+
+// The quirks of the platform from the command line or else the settings, with the
+// saved quirks and then the command line's on top. Quirks saved for one platform
+// mean little on another one, so they are left out when --platform is given
+fn machine(platform: Option<&str>, quirks: &[String], settings: &Settings) -> Result<Quirks, String> {
+    let (platform, saved) = match platform {
+        Some(platform) => (platform, None),
+        None => (settings.platform.as_deref().unwrap_or("default"), settings.quirks.as_deref()),
+    };
+    let mut machine = Quirks::platform(platform)?;
+    for list in saved.into_iter().chain(quirks.iter().map(String::as_str)) {
+        machine.apply(list)?;
+    }
+    return Ok(machine);
+}
+
+// The path as given, or the file of that name in programs/ like before paths could be given
+fn find_program(path: &str) -> Result<String, String> {
+    if Path::new(path).is_file() {
+        return Ok(path.to_string());
+    }
+    let in_programs = Path::new("programs").join(path);
+    if in_programs.is_file() {
+        return Ok(in_programs.to_string_lossy().into_owned());
+    }
+    return Err(format!("There is no rom at '{}' or '{}'", path, in_programs.display()));
+}
+
+fn parse_value<'a>(option: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    return match value {
+        Some(value) => Ok(value),
+        None => Err(format!("{} needs a value", option)),
+    };
+}
+
+fn parse_number(option: &str, value: Option<&String>) -> Result<u32, String> {
+    return parse_value(option, value)?.parse::<u32>().map_err(|_| format!("{} must be a number", option));
+}
+
+fn parse_fraction(option: &str, value: Option<&String>) -> Result<f32, String> {
+    return match parse_value(option, value)?.parse::<f32>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("{} must be a number from 0 to 1", option)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // The settings file, then the database, then the program's own settings and
+    // then the command line, each one's quirks on top of the ones before
+    #[test]
+    fn settings_add_up_in_order() {
+        let folder = std::env::temp_dir().join(format!("chip8-test-{}-options", process::id()));
+        let program = [0x12, 0x00];
+        fs::create_dir_all(folder.join("roms")).unwrap();
+        fs::write(folder.join("settings.toml"), "quirks = \"jump\"\nipf = 15\nscale = 3\npalette = \"amber\"\n").unwrap();
+        let rom_file = folder.join("roms").join(format!("{}.toml", chip8::settings::sha1(&program)));
+        fs::write(&rom_file, "quirks = \"shift\"\nipf = 30\n").unwrap();
+        let entry = Entry {
+            title: String::from("Loop"),
+            authors: Vec::new(),
+            platform: Some(String::from("vip")),
+            quirks: Some(String::from("-clip")),
+            ipf: Some(20),
+            keys: Vec::new(),
+        };
+
+        let (settings, path) = Settings::for_program_in(Some(&folder), &program, Some(entry.settings())).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(path, Some(rom_file));
+        assert_eq!(settings.ipf, Some(30));
+        assert_eq!(settings.scale, Some(3));
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+        assert_eq!(settings.platform.as_deref(), Some("vip"));
+
+        // The database picks a platform, so the settings file's jump is left out
+        let cli = vec![String::from("-vf_reset")];
+        assert_eq!(machine(None, &cli, &settings).unwrap().describe(), "shift,display_wait");
+        assert_eq!(machine(None, &[], &settings).unwrap().describe(), "shift,vf_reset,display_wait");
+        assert_eq!(machine(Some("schip"), &cli, &settings).unwrap().describe(), "shift,load_store,jump,clip");
+    }
+}
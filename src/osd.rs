@@ -0,0 +1,166 @@
+use super::framebuffer::FrameBuffer;
+use std::time::{Duration, Instant};
+
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+// 3x5 pixel font, every row is 3 bits with the leftmost pixel in the highest bit
+const FONT: [(char, [u8; 5]); 50] = [
+    ('0', [7, 5, 5, 5, 7]), ('1', [2, 6, 2, 2, 7]), ('2', [7, 1, 7, 4, 7]), ('3', [7, 1, 7, 1, 7]),
+    ('4', [5, 5, 7, 1, 1]), ('5', [7, 4, 7, 1, 7]), ('6', [7, 4, 7, 5, 7]), ('7', [7, 1, 1, 1, 1]),
+    ('8', [7, 5, 7, 5, 7]), ('9', [7, 5, 7, 1, 7]), ('A', [2, 5, 7, 5, 5]), ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]), ('D', [6, 5, 5, 5, 6]), ('E', [7, 4, 6, 4, 7]), ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]), ('H', [5, 5, 7, 5, 5]), ('I', [7, 2, 2, 2, 7]), ('J', [1, 1, 1, 5, 2]),
+    ('K', [5, 5, 6, 5, 5]), ('L', [4, 4, 4, 4, 7]), ('M', [5, 7, 7, 5, 5]), ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]), ('P', [6, 5, 6, 4, 4]), ('Q', [2, 5, 5, 6, 3]), ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]), ('T', [7, 2, 2, 2, 2]), ('U', [5, 5, 5, 5, 7]), ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]), ('X', [5, 5, 2, 5, 5]), ('Y', [5, 5, 2, 2, 2]), ('Z', [7, 1, 2, 4, 7]),
+    (' ', [0, 0, 0, 0, 0]), ('.', [0, 0, 0, 0, 2]), (':', [0, 2, 0, 2, 0]), ('-', [0, 0, 7, 0, 0]),
+    ('/', [1, 1, 2, 4, 4]), ('%', [5, 1, 2, 4, 5]), ('[', [6, 4, 4, 4, 6]), (']', [3, 1, 1, 1, 3]),
+    ('(', [2, 4, 4, 4, 2]), (')', [2, 1, 1, 1, 2]), (',', [0, 0, 0, 2, 4]), ('!', [2, 2, 2, 0, 2]),
+    ('=', [0, 7, 0, 7, 0]), ('+', [0, 2, 7, 2, 0]),
+];
+
+// The hex keys in the same layout as the keyboard, 1 2 3 C on top like the COSMAC VIP
+pub const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const HIGHLIGHT: [u8; 3] = [0xFF, 0xCC, 0x00];
+
+/*
+    On screen display drawn on top of the image: frames and instructions per
+    second, the speed state, short messages after hotkeys and the keypad with the
+    held keys lit up. Drawn into a copy of the frame so it never shows up in
+    screenshots or recordings
+*/
+pub struct Osd {
+    pub show_stats: bool,
+    pub show_keypad: bool,
+    messages: Vec<(String, Instant)>,       // Text and when it goes away
+    frame: FrameBuffer,
+    sample_start: Instant,                  // Start of the current one second sample
+    sample_frames: u64,
+    sample_instructions: u64,
+    fps: f64,
+    ips: f64,
+}
+
+impl Default for Osd {
+    fn default() -> Osd {
+        return Osd::new();
+    }
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        return Osd {
+            show_stats: false,
+            show_keypad: false,
+            messages: Vec::new(),
+            frame: FrameBuffer::default(),
+            sample_start: Instant::now(),
+            sample_frames: 0,
+            sample_instructions: 0,
+            fps: 0.0,
+            ips: 0.0,
+        };
+    }
+
+    pub fn message(self: &mut Self, text: &str) {
+        self.messages.push((text.to_string(), Instant::now() + MESSAGE_TIME));
+    }
+
+    // True when there is anything to draw, so the frame has to be redrawn every pass
+    pub fn visible(self: &mut Self) -> bool {
+        let now = Instant::now();
+        self.messages.retain(|(_, until)| *until > now);
+        return self.show_stats || self.show_keypad || !self.messages.is_empty();
+    }
+
+    // Call every pass with the totals from the emulator, the rates are worked out once a second
+    pub fn update(self: &mut Self, frame_count: u64, instructions_executed: u64) {
+        let elapsed = self.sample_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.fps = (frame_count - self.sample_frames) as f64 / seconds;
+            self.ips = (instructions_executed - self.sample_instructions) as f64 / seconds;
+            self.sample_start = Instant::now();
+            self.sample_frames = frame_count;
+            self.sample_instructions = instructions_executed;
+        }
+    }
+
+    pub fn draw(self: &mut Self, frame: &FrameBuffer, state: &str, keyboard: u16) -> &FrameBuffer {
+        self.frame.clone_from(frame);
+        let size = (frame.height / 100).max(1);        // Size of a font pixel
+        let line = 6 * size;
+        let margin = size * 2;
+
+        if self.show_stats {
+            let stats = format!("FPS {:.0}  IPS {:.0}", self.fps, self.ips);
+            draw_text(&mut self.frame, &stats, margin, margin, size, WHITE);
+            if !state.is_empty() {
+                let x = frame.width.saturating_sub(text_width(state, size) + margin);
+                draw_text(&mut self.frame, state, x, margin, size, HIGHLIGHT);
+            }
+        }
+
+        let mut y = frame.height.saturating_sub(margin + line * self.messages.len());
+        for (text, _) in &self.messages {
+            draw_text(&mut self.frame, text, margin, y, size, WHITE);
+            y += line;
+        }
+
+        if self.show_keypad {
+            let cell = 6 * size;
+            let left = frame.width.saturating_sub(cell * 4 + margin);
+            let top = frame.height.saturating_sub(cell * 4 + margin);
+            for (i, key) in KEYPAD.iter().enumerate() {
+                let x = left + (i % 4) * cell;
+                let y = top + (i / 4) * cell;
+                let pressed = (keyboard >> key) & 0x01 == 1;
+                let color = if pressed { HIGHLIGHT } else { WHITE };
+                draw_text(&mut self.frame, &format!("{:X}", key), x + size, y, size, color);
+            }
+        }
+        return &self.frame;
+    }
+}
+
+pub fn text_width(text: &str, size: usize) -> usize {
+    return text.chars().count() * 4 * size;
+}
+
+// Draw text with its top left corner at x, y on a darkened box so it can be read on any palette
+pub fn draw_text(frame: &mut FrameBuffer, text: &str, x: usize, y: usize, size: usize, color: [u8; 3]) {
+    let width = text_width(text, size) + size;
+    fill(frame, x.saturating_sub(size), y.saturating_sub(size), width, 7 * size, |pixel| {
+        for channel in pixel.iter_mut().take(3) {
+            *channel /= 3;
+        }
+    });
+    for (i, character) in text.chars().enumerate() {
+        let glyph = match FONT.iter().find(|(c, _)| *c == character.to_ascii_uppercase()) {
+            Some((_, glyph)) => glyph,
+            None => continue,
+        };
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if (bits >> (2 - column)) & 0x01 == 1 {
+                    let px = x + (i * 4 + column) * size;
+                    let py = y + row * size;
+                    fill(frame, px, py, size, size, |pixel| pixel[..3].copy_from_slice(&color));
+                }
+            }
+        }
+    }
+}
+
+pub fn fill<F>(frame: &mut FrameBuffer, x: usize, y: usize, width: usize, height: usize, paint: F)
+    where F: Fn(&mut [u8]) {
+    for py in y..(y + height).min(frame.height) {
+        for px in x..(x + width).min(frame.width) {
+            let i = (py * frame.width + px) * 4;
+            paint(&mut frame.pixels[i..i + 4]);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+/*
+    Maps the logical pixel values in Cpu::display to colors. A value is the set of
+    planes a pixel is lit in, so plain CHIP-8 only uses 0 and 1 while XO-CHIP's two
+    planes give 0 (off), 1 (plane 1), 2 (plane 2) and 3 (both)
+*/
+pub type Color = [u8; 3];
+
+pub const PRESETS: [&str; 5] = ["classic", "amber", "green", "lcd", "octo"];
+
+#[derive(Clone, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub fn preset(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "classic" => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            "amber" => [0x1A0F00, 0xFFB000, 0xB37B00, 0x664600],
+            "green" => [0x0A1A0A, 0x33FF33, 0x22AA22, 0x115511],
+            "lcd" => [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F],
+            "octo" => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            _ => return None,
+        };
+        return Some(Palette { colors: colors.map(rgb) });
+    }
+
+    // Either a preset name or a comma separated list of 2 or 4 hex colors
+    // e.g. "#000000,#FFFFFF" for background and foreground
+    pub fn parse(value: &str) -> Result<Palette, String> {
+        if let Some(palette) = Palette::preset(value) {
+            return Ok(palette);
+        }
+        let colors = value.split(',')
+            .map(parse_hex)
+            .collect::<Result<Vec<Color>, String>>()?;
+        return match colors.len() {
+            // With only two colors both XO-CHIP planes use the foreground
+            2 => Ok(Palette { colors: [colors[0], colors[1], colors[1], colors[1]] }),
+            4 => Ok(Palette { colors: [colors[0], colors[1], colors[2], colors[3]] }),
+            _ => Err(format!("Palette '{}' must be one of {} or 2 or 4 hex colors", value, PRESETS.join(", "))),
+        };
+    }
+
+    pub fn color(self: &Self, value: u8) -> Color {
+        return self.colors[usize::from(value & 0x03)];
+    }
+
+    // Write the display out as RGBA bytes, 4 per pixel
+    pub fn colorize(self: &Self, display: &[u8], out: &mut Vec<u8>) {
+        out.clear();
+        for value in display {
+            let [r, g, b] = self.color(*value);
+            out.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        return Palette::preset("classic").unwrap();
+    }
+}
+
+fn rgb(value: u32) -> Color {
+    return [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+}
+
+fn parse_hex(value: &str) -> Result<Color, String> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("'{}' is not a 6 digit hex color", value));
+    }
+    return u32::from_str_radix(hex, 16)
+        .map(rgb)
+        .map_err(|_| format!("'{}' is not a 6 digit hex color", value));
+}
@@ -0,0 +1,121 @@
+use super::framebuffer::FrameBuffer;
+use super::frontend::InputEvent;
+use super::osd::{self, KEYPAD};
+
+const BACKGROUND: [u8; 3] = [0x20, 0x20, 0x20];
+const KEY: [u8; 3] = [0x50, 0x50, 0x50];
+const PRESSED: [u8; 3] = [0xFF, 0xCC, 0x00];
+const LABEL: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+/*
+    A 4x4 keypad drawn to the right of the display, for touchscreens and the
+    mouse. It is as tall as the display and square, laid out like the COSMAC VIP.
+    Every pointer (the mouse or a finger) presses the key under it, so several
+    fingers can hold several keys, and a key is let go once nothing is on it.
+    Like the on-screen display it is only added to what is shown, never to
+    screenshots or recordings
+*/
+pub struct KeypadPanel {
+    frame: FrameBuffer,
+    left: usize,                    // Where the panel starts in the frame, the display's width
+    cell: usize,
+    pointers: Vec<(u64, u8)>,       // Pointers that are down on a key
+    drawn: Option<u16>,             // The keyboard the panel was last drawn with
+}
+
+impl Default for KeypadPanel {
+    fn default() -> KeypadPanel {
+        return KeypadPanel::new();
+    }
+}
+
+impl KeypadPanel {
+    pub fn new() -> KeypadPanel {
+        return KeypadPanel {
+            frame: FrameBuffer::default(),
+            left: 0,
+            cell: 0,
+            pointers: Vec::new(),
+            drawn: None,
+        };
+    }
+
+    // The size of the frame with the panel added, for sizing the window
+    pub fn size(width: usize, height: usize) -> (usize, usize) {
+        return (width + height, height);
+    }
+
+    // True when the keyboard changed since it was drawn, so it has to be shown again
+    pub fn changed(self: &Self, keyboard: u16) -> bool {
+        return self.drawn != Some(keyboard);
+    }
+
+    pub fn draw(self: &mut Self, frame: &FrameBuffer, keyboard: u16) -> &FrameBuffer {
+        let (width, height) = KeypadPanel::size(frame.width, frame.height);
+        self.frame.width = width;
+        self.frame.height = height;
+        self.frame.pixels.clear();
+        for row in frame.pixels.chunks(frame.width * 4) {
+            self.frame.pixels.extend_from_slice(row);
+            for _ in 0..frame.height {
+                self.frame.pixels.extend_from_slice(&[BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], 0xFF]);
+            }
+        }
+
+        self.left = frame.width;
+        self.cell = frame.height / 4;
+        let gap = (self.cell / 16).max(1);
+        let size = (self.cell / 12).max(1);        // Size of a font pixel
+        for (i, key) in KEYPAD.iter().enumerate() {
+            let x = self.left + (i % 4) * self.cell;
+            let y = (i / 4) * self.cell;
+            let pressed = (keyboard >> key) & 0x01 == 1;
+            let color = if pressed { PRESSED } else { KEY };
+            osd::fill(&mut self.frame, x + gap, y + gap, self.cell - gap * 2, self.cell - gap * 2, |pixel| pixel[..3].copy_from_slice(&color));
+            let label = format!("{:X}", key);
+            let label_x = x + (self.cell - osd::text_width(&label, size)) / 2;
+            let label_y = y + (self.cell - 5 * size) / 2;
+            osd::draw_text(&mut self.frame, &label, label_x, label_y, size, LABEL);
+        }
+        self.drawn = Some(keyboard);
+        return &self.frame;
+    }
+
+    // A pointer went down or moved to a pixel of the frame (None when it is off the frame),
+    // adds the keys that are pressed or let go because of it
+    pub fn point(self: &mut Self, id: u64, position: Option<(usize, usize)>, events: &mut Vec<InputEvent>) {
+        let key = position.and_then(|(x, y)| self.key_at(x, y));
+        let old = self.pointers.iter().position(|(pointer, _)| *pointer == id).map(|i| self.pointers.remove(i).1);
+        if let Some(key) = key {
+            if !self.held(key) && old != Some(key) {
+                events.push(InputEvent::KeyDown(key));
+            }
+            self.pointers.push((id, key));
+        }
+        if let Some(old) = old {
+            if !self.held(old) {
+                events.push(InputEvent::KeyUp(old));
+            }
+        }
+    }
+
+    pub fn release(self: &mut Self, id: u64, events: &mut Vec<InputEvent>) {
+        self.point(id, None, events);
+    }
+
+    fn held(self: &Self, key: u8) -> bool {
+        return self.pointers.iter().any(|(_, held)| *held == key);
+    }
+
+    fn key_at(self: &Self, x: usize, y: usize) -> Option<u8> {
+        if self.cell == 0 || x < self.left {
+            return None;
+        }
+        let column = (x - self.left) / self.cell;
+        let row = y / self.cell;
+        if column >= 4 || row >= 4 {
+            return None;
+        }
+        return Some(KEYPAD[row * 4 + column]);
+    }
+}
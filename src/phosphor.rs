@@ -0,0 +1,102 @@
+use super::palette::Palette;
+
+/*
+    Imitates the slow fade of a phosphor screen. Lit pixels turn on straight away
+    but fade out over a few frames once they are erased, so sprites that are erased
+    and redrawn every frame no longer flicker. Needs to be updated once per emulated
+    frame so the fade runs at the same speed as the game
+*/
+pub struct Phosphor {
+    pub decay: f32,         // How much brightness is kept every frame, 0 turns the effect off
+    levels: Vec<f32>,       // Brightness of every pixel from 0 to 1
+    values: Vec<u8>,        // The last lit value of every pixel so it fades in the same color
+}
+
+impl Phosphor {
+    pub fn new(decay: f32) -> Phosphor {
+        return Phosphor {
+            decay: decay.clamp(0.0, 1.0),
+            levels: Vec::new(),
+            values: Vec::new(),
+        };
+    }
+
+    pub fn update(self: &mut Self, display: &[u8]) {
+        if self.levels.len() != display.len() {        // Resolution changed, start over
+            self.levels = vec![0.0; display.len()];
+            self.values = vec![0; display.len()];
+        }
+        for (i, value) in display.iter().enumerate() {
+            if *value != 0 {
+                self.levels[i] = 1.0;
+                self.values[i] = *value;
+            }
+            else {
+                self.levels[i] *= self.decay;
+            }
+        }
+    }
+
+    // False until the first update after a resolution change
+    pub fn matches(self: &Self, display: &[u8]) -> bool {
+        return self.levels.len() == display.len();
+    }
+
+    // Like Palette::colorize but blending every pixel between the background and its color
+    pub fn colorize(self: &Self, palette: &Palette, out: &mut Vec<u8>) {
+        let background = palette.color(0);
+        out.clear();
+        for (level, value) in self.levels.iter().zip(self.values.iter()) {
+            let color = palette.color(*value);
+            for channel in 0..3 {
+                let blended = f32::from(background[channel]) + (f32::from(color[channel]) - f32::from(background[channel])) * level;
+                out.push(blended.round() as u8);
+            }
+            out.push(0xFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erased_pixels_fade_out() {
+        let mut phosphor = Phosphor::new(0.5);
+        let palette = Palette::parse("#000000,#FF8000").unwrap();
+        let mut out = Vec::new();
+        phosphor.update(&[1, 0]);
+        phosphor.colorize(&palette, &mut out);
+        assert_eq!(out, [0xFF, 0x80, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF]);
+
+        // Half the brightness is kept every frame, in the color the pixel had
+        let mut red = Vec::new();
+        for _ in 0..3 {
+            phosphor.update(&[0, 0]);
+            phosphor.colorize(&palette, &mut out);
+            red.push(out[0]);
+        }
+        assert_eq!(red, [128, 64, 32]);
+        assert_eq!(out[1], 16);
+
+        // Lit again it is at full brightness straight away
+        phosphor.update(&[1, 1]);
+        phosphor.colorize(&palette, &mut out);
+        assert_eq!(&out[..3], &[0xFF, 0x80, 0x00]);
+        assert_eq!(&out[4..7], &[0xFF, 0x80, 0x00]);
+        assert!(!phosphor.matches(&[0; 4]));
+    }
+
+    #[test]
+    fn no_decay_turns_pixels_off_at_once() {
+        let mut phosphor = Phosphor::new(-1.0);
+        assert_eq!(phosphor.decay, 0.0);
+        assert_eq!(Phosphor::new(2.0).decay, 1.0);
+        let mut out = Vec::new();
+        phosphor.update(&[1]);
+        phosphor.update(&[0]);
+        phosphor.colorize(&Palette::default(), &mut out);
+        assert_eq!(&out[..3], &Palette::default().color(0));
+    }
+}
@@ -0,0 +1,281 @@
+use super::framebuffer::FrameBuffer;
+use super::beeper::RECORDING_SAMPLE_RATE;
+use super::timing::FRAME_RATE;
+use super::wav::WavWriter;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const FORMATS: [&str; 4] = ["gif", "apng", "y4m", "wav"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Gif,
+    Apng,
+    Y4m,        // Uncompressed video, for muxing with the audio later
+    Wav,        // Only the beeper, sample for sample what recordings and the libretro core play
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, String> {
+        return match value {
+            "gif" => Ok(Format::Gif),
+            "apng" | "png" => Ok(Format::Apng),
+            "y4m" => Ok(Format::Y4m),
+            "wav" => Ok(Format::Wav),
+            _ => Err(format!("Recording format '{}' must be one of {}", value, FORMATS.join(", "))),
+        };
+    }
+
+    // Pick the format from a file's extension, e.g. out.gif
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        return Format::parse(&extension.to_lowercase());
+    }
+
+    pub fn extension(self: &Self) -> &'static str {
+        return match self {
+            Format::Gif => "gif",
+            Format::Apng => "png",
+            Format::Y4m => "y4m",
+            Format::Wav => "wav",
+        };
+    }
+}
+
+enum Writer {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>, u32),    // And the frames written so far
+    Y4m(BufWriter<File>),
+}
+
+/*
+    Records every emulated frame to a file, and the sound to a WAV file next to
+    it as none of the video formats can hold it. For GIF and APNG identical frames
+    in a row are merged into one longer frame, which keeps the files small as most
+    frames in CHIP-8 games do not change anything. Frames are written as they come
+    so recordings can be as long as there is disk space for
+*/
+pub struct Recorder {
+    path: String,
+    format: Format,
+    writer: Option<Writer>,         // Created on the first frame once we know the size
+    audio: Option<WavWriter>,       // Taken when finishing
+    size: (u32, u32),
+    pending: Option<FrameBuffer>,   // The last frame, held on to until a different one arrives
+    held: u32,                      // How many frames the pending frame was shown for
+    written: u64,                   // Frames already handed to the writer
+}
+
+impl Recorder {
+    pub fn start(path: &str, format: Format) -> Result<Recorder, String> {
+        let audio = match format {
+            Format::Wav => WavWriter::create(path, RECORDING_SAMPLE_RATE)?,
+            _ => WavWriter::create(&Path::new(path).with_extension("wav").to_string_lossy(), RECORDING_SAMPLE_RATE)?,
+        };
+        return Ok(Recorder {
+            path: path.to_string(),
+            format,
+            writer: None,
+            audio: Some(audio),
+            size: (0, 0),
+            pending: None,
+            held: 0,
+            written: 0,
+        });
+    }
+
+    pub fn path(self: &Self) -> &str {
+        return &self.path;
+    }
+
+    // Add one emulated frame and the audio samples generated during it
+    pub fn record_frame(self: &mut Self, frame: &FrameBuffer, audio: &[f32]) -> Result<(), String> {
+        if let Some(wav) = &mut self.audio {
+            wav.write(audio)?;
+        }
+        if self.format == Format::Wav {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            self.size = frame.size();
+            self.writer = Some(self.create_writer()?);
+        }
+        else if frame.size() != self.size {
+            return Err(String::from("The resolution changed while recording"));
+        }
+
+        if let Some(Writer::Y4m(file)) = &mut self.writer {
+            return write_y4m_frame(file, frame).map_err(|e| e.to_string());
+        }
+        match &self.pending {
+            Some(pending) if pending.pixels == frame.pixels => self.held += 1,
+            _ => {
+                self.flush_pending()?;
+                self.pending = Some(frame.clone());
+                self.held = 1;
+            },
+        }
+        return Ok(());
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.flush_pending()?;
+        if let Some(wav) = self.audio.take() {
+            wav.finish()?;
+        }
+        return match self.writer.take() {
+            Some(Writer::Apng(writer, frames)) => {
+                writer.finish().map_err(|e| e.to_string())?;
+                set_apng_frames(&self.path, frames).map_err(|e| format!("Could not finish {}: {}", self.path, e))
+            },
+            Some(Writer::Y4m(mut file)) => file.flush().map_err(|e| e.to_string()),
+            Some(Writer::Gif(_)) | None => Ok(()),     // The GIF trailer is written on drop
+        };
+    }
+
+    fn create_writer(self: &Self) -> Result<Writer, String> {
+        let file = File::create(&self.path).map_err(|e| format!("Could not create {}: {}", self.path, e))?;
+        let mut file = BufWriter::new(file);
+        return match self.format {
+            Format::Apng => {
+                // The number of frames comes before them, so it is filled in by finish
+                let mut encoder = png::Encoder::new(file, self.size.0, self.size.1);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(u32::MAX, 0).map_err(|e| e.to_string())?;
+                Ok(Writer::Apng(encoder.write_header().map_err(|e| e.to_string())?, 0))
+            },
+            Format::Gif => {
+                let mut encoder = gif::Encoder::new(file, self.size.0 as u16, self.size.1 as u16, &[])
+                    .map_err(|e| e.to_string())?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+                Ok(Writer::Gif(encoder))
+            },
+            _ => {
+                writeln!(file, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", self.size.0, self.size.1, FRAME_RATE)
+                    .map_err(|e| e.to_string())?;
+                Ok(Writer::Y4m(file))
+            },
+        };
+    }
+
+    fn flush_pending(self: &mut Self) -> Result<(), String> {
+        let mut frame = match self.pending.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        let start = self.written;
+        self.written += u64::from(self.held);
+        match &mut self.writer {
+            Some(Writer::Gif(encoder)) => {
+                // GIF delays are in 1/100ths of a second, rounding from the start of the
+                // recording each time keeps the total length right
+                let delay = centiseconds(self.written) - centiseconds(start);
+                let mut gif_frame = gif::Frame::from_rgba_speed(self.size.0 as u16, self.size.1 as u16, &mut frame.pixels, 10);
+                gif_frame.delay = delay.min(u64::from(u16::MAX)) as u16;
+                encoder.write_frame(&gif_frame).map_err(|e| e.to_string())?;
+            },
+            Some(Writer::Apng(writer, frames)) => {
+                writer.set_frame_delay(self.held.min(u32::from(u16::MAX)) as u16, FRAME_RATE as u16).map_err(|e| e.to_string())?;
+                writer.write_image_data(&frame.pixels).map_err(|e| e.to_string())?;
+                *frames += 1;
+            },
+            _ => {},
+        }
+        return Ok(());
+    }
+}
+
+fn centiseconds(frames: u64) -> u64 {
+    return (frames * 100 + u64::from(FRAME_RATE) / 2) / u64::from(FRAME_RATE);
+}
+
+// Write the number of frames into the APNG's acTL chunk, which is near the start
+// after the IHDR chunk, and the chunk's CRC to go with it
+fn set_apng_frames(path: &str, frames: u32) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut start = Vec::new();
+    (&file).take(256).read_to_end(&mut start)?;
+    let chunk = match start.windows(4).position(|name| name == b"acTL") {
+        Some(position) if position + 16 <= start.len() => position,
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "no acTL chunk")),
+    };
+    let mut data = start[chunk..chunk + 12].to_vec();      // The name, the frames and the plays
+    data[4..8].copy_from_slice(&frames.to_be_bytes());
+    file.seek(SeekFrom::Start(chunk as u64 + 4))?;
+    file.write_all(&data[4..])?;
+    file.write_all(&crc32(&data).to_be_bytes())?;
+    return file.flush();
+}
+
+// The CRC PNG chunks end with, of the chunk's name and data
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    return !crc;
+}
+
+// Full resolution 4:4:4 YCbCr using the BT.601 studio range
+fn write_y4m_frame(file: &mut BufWriter<File>, frame: &FrameBuffer) -> std::io::Result<()> {
+    let count = frame.width * frame.height;
+    let mut planes = vec![0u8; count * 3];
+    for (i, pixel) in frame.pixels.chunks(4).enumerate() {
+        let (r, g, b) = (f32::from(pixel[0]), f32::from(pixel[1]), f32::from(pixel[2]));
+        planes[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        planes[count + i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        planes[count * 2 + i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+    file.write_all(b"FRAME\n")?;
+    return file.write_all(&planes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    fn frame(lit: bool) -> FrameBuffer {
+        let value = if lit { 255 } else { 0 };
+        return FrameBuffer { width: 4, height: 2, pixels: vec![value; 4 * 2 * 4] };
+    }
+
+    // Frames that are the same as the one before make it longer, and the count
+    // the APNG starts with is filled in at the end
+    #[test]
+    fn apng_frames_are_written_as_they_come() {
+        let path = std::env::temp_dir().join(format!("chip8-test-{}.png", process::id()));
+        let wav_path = path.with_extension("wav");
+        let mut recorder = Recorder::start(&path.to_string_lossy(), Format::Apng).unwrap();
+        for lit in [false, false, true, false, false, false] {
+            recorder.record_frame(&frame(lit), &[0.5; 10]).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+        let mut delays = Vec::new();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        for _ in 0..3 {
+            reader.next_frame(&mut pixels).unwrap();
+            delays.push(reader.info().frame_control.unwrap().delay_num);
+        }
+        let (audio, _) = crate::wav::read(&wav_path.to_string_lossy()).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&wav_path).unwrap();
+        assert_eq!(delays, vec![2, 1, 3]);
+        assert_eq!(audio.len(), 60);
+    }
+
+    #[test]
+    fn crc_matches_png() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+}
@@ -0,0 +1,117 @@
+use super::filter::Filter;
+use super::framebuffer::FrameBuffer;
+use super::palette::Palette;
+use super::phosphor::Phosphor;
+use super::upscale::Upscaler;
+
+/*
+    Turns Cpu::display into the image that is shown, the steps are:
+    display -> pixel art upscaler -> phosphor fade -> palette -> upscale -> filter
+    Plain upscaling is only done here when there is a filter, otherwise the image
+    is handed over at the upscaler's size and the window scales the rest
+*/
+pub struct Renderer {
+    pub palette: Palette,
+    pub phosphor: Option<Phosphor>,
+    pub filter: Filter,
+    pub upscaler: Upscaler,
+    pub scale: usize,
+    logical: Vec<u8>,           // The display after the upscaler, still display values
+    native: FrameBuffer,
+    scaled: FrameBuffer,
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, phosphor: Option<Phosphor>, filter: Filter, upscaler: Upscaler, scale: usize) -> Renderer {
+        return Renderer {
+            palette,
+            phosphor,
+            filter,
+            upscaler,
+            scale: scale.max(1),
+            logical: Vec::new(),
+            native: FrameBuffer::default(),
+            scaled: FrameBuffer::default(),
+        };
+    }
+
+    // Call once for every emulated frame, even ones that are not shown
+    pub fn update(self: &mut Self, display: &[u8], resolution: (usize, usize)) {
+        if let Some(phosphor) = &mut self.phosphor {
+            self.upscaler.apply(display, resolution, &mut self.logical);
+            phosphor.update(&self.logical);
+        }
+    }
+
+    // True when the image changes every frame even if the display did not
+    pub fn animating(self: &Self) -> bool {
+        return self.phosphor.is_some();
+    }
+
+    pub fn render(self: &mut Self, display: &[u8], resolution: (usize, usize)) -> &FrameBuffer {
+        return self.render_at(display, resolution, false);
+    }
+
+    // Always upscaled to the full scale even without a filter, for screenshots and recordings
+    pub fn render_scaled(self: &mut Self, display: &[u8], resolution: (usize, usize)) -> &FrameBuffer {
+        return self.render_at(display, resolution, true);
+    }
+
+    fn render_at(self: &mut Self, display: &[u8], resolution: (usize, usize), scaled: bool) -> &FrameBuffer {
+        let (width, height) = self.upscaler.apply(display, resolution, &mut self.logical);
+        self.native.width = width;
+        self.native.height = height;
+        match &self.phosphor {
+            Some(phosphor) if phosphor.matches(&self.logical) => phosphor.colorize(&self.palette, &mut self.native.pixels),
+            _ => self.palette.colorize(&self.logical, &mut self.native.pixels),
+        }
+        if self.filter == Filter::None && !scaled {
+            return &self.native;
+        }
+        // Whatever the upscaler did not cover, so filters still get scale sized cells. When
+        // the scale is not a multiple of the upscaler's factor (scale 8 with scale3x) the
+        // image is resampled to the scale so it is the same size with every upscaler
+        let factor = self.scale / self.upscaler.factor();
+        if factor >= 1 && factor * self.upscaler.factor() == self.scale {
+            self.native.upscale(factor, &mut self.scaled);
+        } else {
+            self.native.resample(resolution.0 * self.scale, resolution.1 * self.scale, &mut self.scaled);
+        }
+        self.filter.apply(&mut self.scaled, self.scale);
+        return &self.scaled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A display with only its top left pixel on
+    fn display() -> Vec<u8> {
+        let mut display = vec![0; 64 * 32];
+        display[0] = 1;
+        return display;
+    }
+
+    #[test]
+    fn scaled_frames_are_the_requested_size_with_every_upscaler() {
+        for name in crate::upscale::UPSCALERS.iter() {
+            for scale in 1..=9 {
+                let upscaler = Upscaler::parse(name).unwrap();
+                let mut renderer = Renderer::new(Palette::default(), None, Filter::None, upscaler, scale);
+                let frame = renderer.render_scaled(&display(), (64, 32));
+                assert_eq!(frame.size(), (64 * scale as u32, 32 * scale as u32), "{} at scale {}", name, scale);
+            }
+        }
+    }
+
+    #[test]
+    fn resampling_keeps_the_pixels_in_place() {
+        let mut renderer = Renderer::new(Palette::default(), None, Filter::None, Upscaler::parse("scale3x").unwrap(), 8);
+        let frame = renderer.render_scaled(&display(), (64, 32));
+        assert_eq!(frame.pixel(7, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(frame.pixel(0, 7), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(frame.pixel(8, 0), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(frame.pixel(0, 8), [0x00, 0x00, 0x00, 0xFF]);
+    }
+}
@@ -0,0 +1,410 @@
+use super::beeper::{BeeperSettings, FrameAudio};
+use super::emulator::Emulator;
+use super::frontend::{AudioSink, InputEvent, InputSource, VideoSink};
+use super::movie::Movie;
+use super::osd::Osd;
+use super::panel::KeypadPanel;
+use super::record::{Format, Recorder};
+use super::render::Renderer;
+use super::screenshot;
+use super::settings::Settings;
+use super::speed::SpeedControl;
+use super::timing::{self, FrameTimer};
+use super::turbo::InputLayer;
+use std::path::{Path, PathBuf};
+
+pub const TITLE: &str = "Rust-Chip8-Interpreter";
+
+/*
+    The main loop, shared by every frontend. Each pass handles the input, runs this
+    pass's frames (none when paused and several when fast forwarding), sets the
+    beeper and shows the display. Frontends only provide the sinks and the input
+    source from frontend.rs, so nothing in here knows about SDL or terminals
+*/
+pub struct Runner {
+    pub chip8: Emulator,
+    pub renderer: Renderer,
+    pub osd: Osd,
+    pub speed: SpeedControl,
+    pub program: String,            // Screenshots and recordings are named after it
+    pub record_format: Format,      // Format used when recording is toggled on
+    pub beeper: BeeperSettings,     // How the beeper sounds in recordings
+    pub movie: Option<Movie>,       // Every keyboard the Cpu gets is recorded into it when set
+    pub throttle: bool,             // Wait for every frame's deadline, off to run as fast as possible
+    pub frame_limit: Option<u64>,   // Stop once this many frames have run
+    pub panel: Option<KeypadPanel>, // A keypad to click or touch, drawn next to the display
+    pub input: InputLayer,          // Keys held, turbo buttons and macros, turned into the keyboard every frame
+    pub settings_file: Option<PathBuf>, // Where the program's own settings are saved to, None to not save them
+    pub title: String,              // Shown before the speed in the window title
+    recorder: Option<(Recorder, FrameAudio)>,
+    redraw: bool,                   // Window was resized or uncovered
+    shown_title: String,
+}
+
+impl Runner {
+    pub fn new(chip8: Emulator, renderer: Renderer, program: &str) -> Runner {
+        return Runner {
+            chip8,
+            renderer,
+            osd: Osd::new(),
+            speed: SpeedControl::new(0),
+            program: program.to_string(),
+            record_format: Format::Gif,
+            beeper: BeeperSettings::default(),
+            movie: None,
+            throttle: true,
+            frame_limit: None,
+            panel: None,
+            input: InputLayer::default(),
+            settings_file: None,
+            title: TITLE.to_string(),
+            recorder: None,
+            redraw: true,
+            shown_title: String::new(),
+        };
+    }
+
+    pub fn start_recording(self: &mut Self, path: &str, format: Format) -> Result<(), String> {
+        self.recorder = Some((Recorder::start(path, format)?, FrameAudio::new(&self.beeper)));
+        return Ok(());
+    }
+
+    pub fn stop_recording(self: &mut Self) -> Result<(), String> {
+        return match self.recorder.take() {
+            Some((recording, _)) => recording.finish(),
+            None => Ok(()),
+        };
+    }
+
+    // Runs until the input quits or the frame limit is reached, then finishes any recording
+    pub fn run(self: &mut Self, video: &mut dyn VideoSink, audio: &mut dyn AudioSink, input: &mut dyn InputSource) -> Result<(), String> {
+        let mut frame_timer = FrameTimer::new(timing::FRAME_RATE);
+        let mut events = Vec::new();
+
+        'running: while !self.finished() {
+            // Handle input events once per pass
+            events.clear();
+            input.poll(&mut events);
+            for event in &events {
+                if *event == InputEvent::Quit {
+                    break 'running;
+                }
+                self.handle(*event, video);
+            }
+
+            self.run_frames();
+
+            // Play or Pause sound? Stay quiet while paused
+            let mut tone = self.chip8.tone();
+            tone.beeping &= !self.speed.paused;
+            audio.set_tone(&tone);
+
+            self.present(video);
+
+            if self.throttle && !self.speed.unlimited() {
+                frame_timer.wait();     // Sleep until the next frame is due
+            }
+        }
+        return self.stop_recording();
+    }
+
+    fn finished(self: &Self) -> bool {
+        return self.frame_limit.is_some_and(|limit| self.chip8.frame_count >= limit);
+    }
+
+    fn handle(self: &mut Self, event: InputEvent, video: &mut dyn VideoSink) {
+        match event {
+            InputEvent::KeyDown(key) => {
+                self.input.press(key);
+                self.update_keyboard();
+            },
+            InputEvent::KeyUp(key) => {
+                self.input.release(key);
+                self.update_keyboard();
+            },
+            InputEvent::Turbo(turbo, held) => {
+                self.input.turbo(turbo, held, self.chip8.frame_count);
+                self.update_keyboard();
+            },
+            InputEvent::Macro(number) => {
+                self.input.play(number, self.chip8.frame_count);
+                self.update_keyboard();
+            },
+            InputEvent::Quit => {},
+            InputEvent::TogglePause => {
+                self.speed.toggle_pause();
+                self.osd.message(if self.speed.paused { "Paused" } else { "Resumed" });
+            },
+            InputEvent::Step => self.speed.step(),
+            InputEvent::FastForward(held) => self.speed.fast_forward = held,
+            InputEvent::Faster | InputEvent::Slower => {
+                self.chip8.change_speed(event == InputEvent::Faster);
+                self.osd.message(&format!("{} IPF", self.chip8.instructions_per_frame));
+            },
+            InputEvent::ToggleFullscreen => video.toggle_fullscreen(),
+            InputEvent::NextUpscaler => {
+                self.renderer.upscaler = self.renderer.upscaler.next();
+                self.osd.message(&format!("Upscaler {}", self.renderer.upscaler.name()));
+                self.redraw = true;
+            },
+            InputEvent::ToggleStats => self.osd.show_stats = !self.osd.show_stats,
+            InputEvent::ToggleKeypad => self.osd.show_keypad = !self.osd.show_keypad,
+            InputEvent::ToggleRecording => self.toggle_recording(),
+            InputEvent::SaveSettings => self.save_settings(),
+            InputEvent::Screenshot => self.screenshot(false),
+            InputEvent::NativeScreenshot => self.screenshot(true),
+            InputEvent::Redraw => self.redraw = true,
+            InputEvent::PointerDown(id, x, y) | InputEvent::PointerMotion(id, x, y) => {
+                let position = video.frame_position(x, y);
+                self.panel_keys(video, |panel, keys| panel.point(id, position, keys));
+            },
+            InputEvent::PointerUp(id) => self.panel_keys(video, |panel, keys| panel.release(id, keys)),
+        }
+    }
+
+    // Press and let go of the keys the pointers on the panel changed
+    fn panel_keys<F>(self: &mut Self, video: &mut dyn VideoSink, change: F)
+        where F: FnOnce(&mut KeypadPanel, &mut Vec<InputEvent>) {
+        let mut keys = Vec::new();
+        if let Some(panel) = &mut self.panel {
+            change(panel, &mut keys);
+        }
+        for key in keys {
+            self.handle(key, video);
+        }
+    }
+
+    // Show the keys straight away rather than at the next frame, which matters while paused
+    fn update_keyboard(self: &mut Self) {
+        let keyboard = self.input.keyboard(self.chip8.frame_count);
+        self.chip8.cpu.set_keyboard(keyboard);
+        if let Some(movie) = &mut self.movie {
+            movie.record(keyboard);
+        }
+    }
+
+    fn toggle_recording(self: &mut Self) {
+        if let Some((recording, _)) = &self.recorder {
+            let path = recording.path().to_string();
+            match self.stop_recording() {
+                Ok(()) => self.osd.message(&format!("Saved {}", path)),
+                Err(e) => self.osd.message(&format!("Recording failed: {}", e)),
+            }
+            return;
+        }
+        let name = screenshot::file_name(&self.program, self.chip8.frame_count);
+        let path = Path::new(&name).with_extension(self.record_format.extension());
+        match self.start_recording(&path.to_string_lossy(), self.record_format) {
+            Ok(()) => self.osd.message("Recording"),
+            Err(e) => self.osd.message(&format!("Recording failed: {}", e)),
+        }
+    }
+
+    // Keep the speed and upscaler picked with the hotkeys for the next time the program runs
+    fn save_settings(self: &mut Self) {
+        let path = match &self.settings_file {
+            Some(path) => path.clone(),
+            None => return self.osd.message("Settings can not be saved here"),
+        };
+        let result = Settings::load(&path).and_then(|mut settings| {
+            settings.rom = settings.rom.or_else(|| Path::new(&self.program).file_name().map(|name| name.to_string_lossy().into_owned()));
+            settings.ipf = Some(self.chip8.instructions_per_frame);
+            settings.upscaler = Some(self.renderer.upscaler.name().to_string());
+            return settings.save(&path);
+        });
+        match result {
+            Ok(()) => self.osd.message("Saved settings"),
+            Err(e) => self.osd.message(&format!("Could not save settings: {}", e)),
+        }
+    }
+
+    // Saves what is on screen, or the display at its native resolution
+    fn screenshot(self: &mut Self, native: bool) {
+        let name = screenshot::file_name(&self.program, self.chip8.frame_count);
+        let result = if native {
+            self.chip8.screenshot(Path::new(&name), &self.renderer.palette, 1)
+        } else {
+            let frame = self.renderer.render_scaled(&(self.chip8.cpu.display), self.chip8.cpu.resolution());
+            screenshot::save_png(Path::new(&name), frame)
+        };
+        match result {
+            Ok(()) => self.osd.message(&format!("Saved {}", name)),
+            Err(e) => self.osd.message(&format!("Screenshot failed: {}", e)),
+        }
+    }
+
+    fn run_frames(self: &mut Self) {
+        for _ in 0..self.speed.frames_to_run() {
+            if self.finished() {
+                break;
+            }
+            self.update_keyboard();             // Turbo buttons and macros change it by the frame
+            if let Some(movie) = &mut self.movie {
+                movie.end_frame(self.chip8.cpu.keyboard);
+            }
+            self.chip8.run_frame();
+            self.renderer.update(&(self.chip8.cpu.display), self.chip8.cpu.resolution());  // Fade once per emulated frame
+            self.redraw |= self.renderer.animating();
+
+            if let Some((recording, audio)) = &mut self.recorder {
+                let samples = audio.next_frame(&self.chip8.tone());
+                let frame = self.renderer.render_scaled(&(self.chip8.cpu.display), self.chip8.cpu.resolution());
+                if let Err(e) = recording.record_frame(frame, &samples) {
+                    self.osd.message(&format!("Recording failed: {}", e));
+                    self.recorder = None;
+                }
+            }
+        }
+    }
+
+    fn present(self: &mut Self, video: &mut dyn VideoSink) {
+        // Show the current speed in the title, frontends without a window draw it
+        // with the frame so it needs a redraw too
+        let title = format!("{} - {}", self.title, self.speed.describe(self.chip8.instructions_per_frame));
+        if title != self.shown_title {
+            video.set_title(&title);
+            self.shown_title = title;
+            self.redraw = true;
+        }
+
+        // Update the display if needed, the overlay is drawn at the full scale so the text can be read
+        self.osd.update(self.chip8.frame_count, self.chip8.instructions_executed);
+        let overlay = video.overlay() && self.osd.visible();
+        let keyboard = self.chip8.cpu.keyboard;
+        let panel_changed = self.panel.as_ref().is_some_and(|panel| panel.changed(keyboard));
+        if self.chip8.cpu.draw_flag == 1 || self.redraw || overlay || panel_changed {
+            let display = &(self.chip8.cpu.display);
+            let resolution = self.chip8.cpu.resolution();
+            let mut frame = if overlay || self.panel.is_some() {
+                self.renderer.render_scaled(display, resolution)
+            } else {
+                self.renderer.render(display, resolution)       // Colors and filters
+            };
+            if overlay {
+                frame = self.osd.draw(frame, &self.speed.state(), keyboard);
+            }
+            if let Some(panel) = &mut self.panel {
+                frame = panel.draw(frame, keyboard);
+            }
+            video.present(frame);
+            self.chip8.cpu.draw_flag = 0;
+            self.redraw = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::framebuffer::FrameBuffer;
+    use crate::frontend::{CapturedAudio, CapturedVideo, NullAudio, NullInput, NullVideo, ScriptedInput};
+    use crate::movie::{Movie, MoviePlayer};
+    use std::fs;
+    use std::process;
+    use crate::palette::Palette;
+    use crate::upscale::Upscaler;
+
+    // Waits for a key, draws its digit in the top left corner and beeps for 5 frames
+    const PROGRAM: [u8; 14] = [
+        0xF0, 0x0A,     // LD V0, K
+        0xF0, 0x29,     // LD F, V0
+        0x62, 0x00,     // LD V2, 0
+        0xD2, 0x25,     // DRW V2, V2, 5
+        0x63, 0x05,     // LD V3, 5
+        0xF3, 0x18,     // LD ST, V3
+        0x12, 0x0C,     // JP 0x20C
+    ];
+
+    fn runner() -> Runner {
+        let mut chip8 = Emulator::new(11);
+        chip8.cpu.load_sprites();
+        chip8.cpu.load_bytes(&PROGRAM).unwrap();
+        let renderer = Renderer::new(Palette::default(), None, Filter::None, Upscaler::None, 1);
+        let mut runner = Runner::new(chip8, renderer, "test.ch8");
+        runner.throttle = false;
+        return runner;
+    }
+
+    // Whether the top left pixel of the digit is drawn
+    fn lit(frame: &FrameBuffer) -> bool {
+        return frame.pixel(0, 0) == [0xFF, 0xFF, 0xFF, 0xFF];
+    }
+
+    #[test]
+    fn draws_the_pressed_key_and_beeps() {
+        let mut runner = runner();
+        let mut video = CapturedVideo::default();
+        let mut audio = CapturedAudio::default();
+        let mut passes = vec![vec![], vec![InputEvent::KeyDown(7)], vec![InputEvent::KeyUp(7)]];
+        passes.resize(10, vec![]);
+        runner.run(&mut video, &mut audio, &mut ScriptedInput::new(passes)).unwrap();
+
+        assert_eq!(runner.chip8.frame_count, 10);
+        assert_eq!(runner.chip8.cpu.registers[0], 7);
+        assert!(!lit(&video.frames[0]));
+        assert!(lit(video.frames.last().unwrap()));
+        assert_eq!(video.frames[0].size(), (64, 32));
+        assert!(video.title.starts_with(TITLE));
+        assert_eq!(audio.beeps(), 1);
+        let beeping: Vec<bool> = audio.passes.iter().map(|tone| tone.beeping).collect();
+        assert_eq!(beeping, [false, true, true, true, true, true, false, false, false, false]);
+    }
+
+    // Stepping runs a frame but the beeper stays quiet until the pause ends
+    #[test]
+    fn pausing_stops_the_frames_and_the_beeper() {
+        let mut runner = runner();
+        let mut video = CapturedVideo::default();
+        let mut audio = CapturedAudio::default();
+        let passes = vec![
+            vec![],
+            vec![InputEvent::KeyDown(7)],
+            vec![InputEvent::TogglePause],
+            vec![],
+            vec![InputEvent::Step],
+            vec![InputEvent::TogglePause],
+        ];
+        runner.run(&mut video, &mut audio, &mut ScriptedInput::new(passes)).unwrap();
+
+        assert_eq!(runner.chip8.frame_count, 4);
+        assert!(lit(video.frames.last().unwrap()));
+        let beeping: Vec<bool> = audio.passes.iter().map(|tone| tone.beeping).collect();
+        assert_eq!(beeping, [false, true, false, false, false, true]);
+        assert_eq!(audio.beeps(), 2);
+    }
+
+    #[test]
+    fn stops_at_the_frame_limit() {
+        let mut runner = runner();
+        runner.frame_limit = Some(4);
+        let mut video = CapturedVideo::default();
+        let mut audio = CapturedAudio::default();
+        runner.run(&mut video, &mut audio, &mut NullInput).unwrap();
+
+        assert_eq!(runner.chip8.frame_count, 4);
+        assert_eq!(audio.passes.len(), 4);
+        assert_eq!(audio.beeps(), 0);
+        assert!(!lit(video.frames.last().unwrap()));
+    }
+
+    // Rendering a movie to .wav the way chip8 render does, twice, gives the same bytes
+    #[test]
+    fn wav_renders_are_the_same_every_time() {
+        let movie = "chip8-movie 2\nseed 1\n0000\n0080 0000\n0000\n0000\n0000\n0000\n0000\n0000\n";
+        let mut renders = Vec::new();
+        for render in 0..2 {
+            let movie = Movie::parse(movie).unwrap();
+            let mut runner = runner();
+            movie.apply(&mut runner.chip8);
+            runner.frame_limit = Some(movie.frames.len() as u64);
+            let path = std::env::temp_dir().join(format!("chip8-test-{}-{}.wav", process::id(), render));
+            runner.start_recording(&path.to_string_lossy(), Format::Wav).unwrap();
+            runner.run(&mut NullVideo, &mut NullAudio, &mut MoviePlayer::new(movie)).unwrap();
+            renders.push(fs::read(&path).unwrap());
+            fs::remove_file(&path).unwrap();
+        }
+        assert_eq!(renders[0], renders[1]);
+        assert!(renders[0][44..].iter().any(|byte| *byte != 0));
+    }
+}
@@ -0,0 +1,172 @@
+use super::cpu::Rng;
+use super::emulator::Emulator;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 3;
+
+// Every state is the same size, libretro frontends ask for it before saving
+pub const STATE_SIZE: usize = 4 + 1                 // Magic and version
+    + 4096 + 16 + 2 + 2 + 24 * 2 + 1                // Memory, registers, I, pc, stack, stack level
+    + 1 + 1 + 2 + 1                                 // Timers, keyboard, waiting for a key
+    + 64 * 32 + 1 + 1                               // Display, draw flag, waiting for the next frame
+    + 1 + 16 + 1                                    // Audio pattern and pitch
+    + 8                                             // Random number generator
+    + 8;                                            // Frame count
+
+/*
+    Save states, a snapshot of everything the Cpu needs to carry on from where it
+    was. The quirks and the speed are settings rather than state so they are left
+    out, loading a state keeps whatever is set now
+*/
+pub fn save(chip8: &Emulator) -> Vec<u8> {
+    let cpu = &chip8.cpu;
+    let mut state = Vec::with_capacity(STATE_SIZE);
+    state.extend_from_slice(MAGIC);
+    state.push(VERSION);
+    state.extend_from_slice(&cpu.memory);
+    state.extend_from_slice(&cpu.registers);
+    state.extend_from_slice(&cpu.address_register.to_le_bytes());
+    state.extend_from_slice(&cpu.program_counter.to_le_bytes());
+    for address in cpu.stack.iter() {
+        state.extend_from_slice(&address.to_le_bytes());
+    }
+    state.push(cpu.stack_counter as u8);
+    state.push(cpu.delay_timer);
+    state.push(cpu.sound_timer);
+    state.extend_from_slice(&cpu.keyboard.to_le_bytes());
+    state.push(u8::from(cpu.waiting_for_key_flag));
+    state.extend_from_slice(&cpu.display);
+    state.push(cpu.draw_flag);
+    state.push(u8::from(cpu.waiting_for_vblank));
+    state.push(u8::from(cpu.audio_pattern.is_some()));
+    state.extend_from_slice(&cpu.audio_pattern.unwrap_or([0; 16]));
+    state.push(cpu.pitch);
+    state.extend_from_slice(&cpu.rng.state.to_le_bytes());
+    state.extend_from_slice(&chip8.frame_count.to_le_bytes());
+    return state;
+}
+
+pub fn load(chip8: &mut Emulator, state: &[u8]) -> Result<(), String> {
+    if state.len() != STATE_SIZE || &state[..4] != MAGIC {
+        return Err(String::from("Not a save state"));
+    }
+    if state[4] != VERSION {
+        return Err(format!("Save state version {} is not supported", state[4]));
+    }
+    let mut reader = Reader { state, position: 5 };
+    let memory = reader.bytes(4096);
+    let registers = reader.bytes(16);
+    let address_register = reader.word();
+    let program_counter = reader.word();
+    let mut stack = [0; 24];
+    for address in stack.iter_mut() {
+        *address = reader.word();
+    }
+    // The next instruction would read outside memory, checked before anything is changed
+    if address_register > 0xFFF || program_counter > 0xFFE || stack.iter().any(|address| *address > 0xFFE) {
+        return Err(String::from("The save state has addresses outside memory"));
+    }
+    let cpu = &mut chip8.cpu;
+    cpu.memory.copy_from_slice(memory);
+    cpu.registers.copy_from_slice(registers);
+    cpu.address_register = address_register;
+    cpu.program_counter = program_counter;
+    cpu.stack = stack;
+    cpu.stack_counter = usize::from(reader.byte()).min(cpu.stack.len());
+    cpu.delay_timer = reader.byte();
+    cpu.sound_timer = reader.byte();
+    cpu.keyboard = reader.word();
+    cpu.waiting_for_key_flag = reader.byte() != 0;
+    cpu.display.copy_from_slice(reader.bytes(64 * 32));
+    cpu.draw_flag = 1;                                  // Whatever is on screen is out of date
+    reader.byte();
+    cpu.waiting_for_vblank = reader.byte() != 0;
+    let has_pattern = reader.byte() != 0;
+    let mut pattern = [0; 16];
+    pattern.copy_from_slice(reader.bytes(16));
+    cpu.audio_pattern = if has_pattern { Some(pattern) } else { None };
+    cpu.pitch = reader.byte();
+    cpu.rng = Rng::new(reader.long());
+    chip8.frame_count = reader.long();
+    return Ok(());
+}
+
+struct Reader<'a> {
+    state: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(self: &mut Self, count: usize) -> &'a [u8] {
+        let bytes = &self.state[self.position..self.position + count];
+        self.position += count;
+        return bytes;
+    }
+
+    fn byte(self: &mut Self) -> u8 {
+        return self.bytes(1)[0];
+    }
+
+    fn word(self: &mut Self) -> u16 {
+        let bytes = self.bytes(2);
+        return u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+
+    fn long(self: &mut Self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8));
+        return u64::from_le_bytes(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 6005 A20A F033 D015 1208, writes 5 out as digits, draws them and loops
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0xA2, 0x0A, 0xF0, 0x33, 0xD0, 0x15, 0x12, 0x08];
+
+    fn emulator() -> Emulator {
+        let mut chip8 = Emulator::new(11);
+        chip8.cpu.load_sprites();
+        chip8.cpu.load_bytes(&PROGRAM).unwrap();
+        chip8.cpu.seed(7);
+        return chip8;
+    }
+
+    #[test]
+    fn states_load_back() {
+        let mut chip8 = emulator();
+        chip8.run_frame();
+        let state = save(&chip8);
+        assert_eq!(state.len(), STATE_SIZE);
+
+        let mut loaded = Emulator::new(11);
+        load(&mut loaded, &state).unwrap();
+        assert_eq!(save(&loaded), state);
+        for _ in 0..3 {
+            chip8.run_frame();
+            loaded.run_frame();
+        }
+        assert_eq!(save(&loaded), save(&chip8));
+        assert_eq!(loaded.cpu.display, chip8.cpu.display);
+    }
+
+    #[test]
+    fn broken_states_change_nothing() {
+        let mut chip8 = emulator();
+        chip8.run_frame();
+        let before = save(&chip8);
+        let program_counter = 4 + 1 + 4096 + 16 + 2;
+        for (position, bytes) in [(program_counter, [0xFF, 0x0F]), (program_counter - 2, [0x00, 0x10]), (program_counter + 2, [0xFF, 0xFF])] {
+            let mut state = before.clone();
+            state[position..position + 2].copy_from_slice(&bytes);
+            assert!(load(&mut chip8, &state).is_err());
+            assert_eq!(save(&chip8), before);
+        }
+        assert!(load(&mut chip8, &before[1..]).is_err());
+        let mut state = before.clone();
+        state[4] = VERSION + 1;
+        assert!(load(&mut chip8, &state).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use chip8::framebuffer::FrameBuffer;
+use chip8::frontend::VideoSink;
+use super::options::Scaling;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+
+/*
+    Owns the window's canvas and the streaming texture the display is copied
+    into, and draws it centered in the window with black bars around it. The
+    texture is recreated whenever the size of what we are drawing changes (e.g.
+    switching to hires), so the window itself never needs to be rebuilt
+*/
+pub struct Screen<'a> {
+    canvas: Canvas<Window>,
+    creator: &'a TextureCreator<WindowContext>,
+    texture: Texture<'a>,
+    texture_size: (u32, u32),
+    target: Rect,                   // Where the last frame was drawn in the window
+    pub scaling: Scaling,
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(canvas: Canvas<Window>, creator: &'a TextureCreator<WindowContext>, scaling: Scaling) -> Screen<'a> {
+        return Screen {
+            canvas,
+            creator,
+            texture: create_texture(creator, (1, 1)),
+            texture_size: (1, 1),
+            target: Rect::new(0, 0, 1, 1),
+            scaling,
+        };
+    }
+}
+
+impl VideoSink for Screen<'_> {
+    // Draw the frame to the canvas and present it
+    fn present(&mut self, frame: &FrameBuffer) {
+        let size = frame.size();
+        if size != self.texture_size {
+            self.texture = create_texture(self.creator, size);
+            self.texture_size = size;
+        }
+        self.texture.update(None, &frame.pixels, frame.width * 4).unwrap();
+
+        let (window_width, window_height) = self.canvas.output_size().unwrap();
+        self.target = letterbox((window_width, window_height), size, self.scaling);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, Some(self.target)).unwrap();
+        self.canvas.present();
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).unwrap();
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen).unwrap();
+    }
+
+    fn frame_position(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let (window_width, window_height) = self.canvas.output_size().ok()?;
+        let x = x * window_width as f32 - self.target.x() as f32;
+        let y = y * window_height as f32 - self.target.y() as f32;
+        let (width, height) = (self.target.width() as f32, self.target.height() as f32);
+        if x < 0.0 || y < 0.0 || x >= width || y >= height {
+            return None;
+        }
+        return Some(((x / width * self.texture_size.0 as f32) as usize, (y / height * self.texture_size.1 as f32) as usize));
+    }
+}
+
+// The largest rectangle with the content's aspect ratio that fits in the window, centered
+pub fn letterbox(window: (u32, u32), content: (u32, u32), scaling: Scaling) -> Rect {
+    let scale_x = window.0 as f32 / content.0 as f32;
+    let scale_y = window.1 as f32 / content.1 as f32;
+    let mut scale = scale_x.min(scale_y);
+    if scaling == Scaling::Integer {
+        scale = scale.floor().max(1.0);     // Never smaller than 1 even if it gets cropped
+    }
+    let width = (content.0 as f32 * scale) as u32;
+    let height = (content.1 as f32 * scale) as u32;
+    let x = (window.0 as i32 - width as i32) / 2;
+    let y = (window.1 as i32 - height as i32) / 2;
+    return Rect::new(x, y, width, height);
+}
+
+fn create_texture(creator: &TextureCreator<WindowContext>, size: (u32, u32)) -> Texture<'_> {
+    return creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, size.0, size.1)
+        .map_err(|e| e.to_string()).unwrap();
+}
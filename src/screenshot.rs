@@ -0,0 +1,20 @@
+use super::framebuffer::FrameBuffer;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// Screenshots are named after the program and the frame they were taken on, e.g. pong-1234.png
+pub fn file_name(program: &str, frame: u64) -> String {
+    let stem = Path::new(program).file_stem().and_then(|stem| stem.to_str()).unwrap_or("chip8");
+    return format!("{}-{}.png", stem, frame);
+}
+
+pub fn save_png(path: &Path, frame: &FrameBuffer) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&frame.pixels).map_err(|e| e.to_string())?;
+    return Ok(());
+}
@@ -0,0 +1,219 @@
+use super::cpu::Quirks;
+use super::filter::Filter;
+use super::palette::Palette;
+use super::upscale::Upscaler;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+    Settings kept between runs, in the user's config folder (~/.config/chip8 on
+    Linux). settings.toml has the defaults for every program and roms/<sha1>.toml
+    the settings for one program, found by the SHA-1 of its bytes so they follow
+    the program when it is renamed or moved. A program's own settings win over
+    the defaults and the command line wins over both. Quirks add up, each one's on
+    top of the ones under it, unless it also picks a platform. Everything is optional:
+
+        platform = "vip"
+        quirks = "-display_wait"
+        ipf = 15
+        scale = 10
+        palette = "amber"
+        volume = 20
+        keymap = "numpad"
+
+        [keys]
+        5 = ["Space"]
+*/
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub rom: Option<String>,            // The file a program's settings were saved from, only for people reading them
+    pub platform: Option<String>,
+    pub quirks: Option<String>,         // Applied on top of the platform, like --quirks
+    pub ipf: Option<u32>,
+    pub scale: Option<u32>,
+    pub scaling: Option<String>,
+    pub palette: Option<String>,
+    pub phosphor: Option<f32>,
+    pub filter: Option<String>,
+    pub upscaler: Option<String>,
+    pub volume: Option<u32>,            // Percent
+    pub keymap: Option<String>,         // A preset or a keymap file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>,    // Host keys for hex keys on top of the keymap, as in keymap files
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pad: BTreeMap<String, Vec<String>>,
+}
+
+impl Settings {
+    // The settings in a file, or none at all when there is no file yet
+    pub fn load(path: &Path) -> Result<Settings, String> {
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let settings: Settings = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings.check().map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(settings);
+    }
+
+    pub fn save(self: &Self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder).map_err(|e| format!("Could not create {}: {}", folder.display(), e))?;
+        }
+        return fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e));
+    }
+
+    // The default settings, then what the database knows about the program and the
+    // program's own settings on top, and the file the program's own are saved to
+    // (None when there is no config folder)
+    pub fn for_program(program: &[u8], known: Option<Settings>) -> Result<(Settings, Option<PathBuf>), String> {
+        return Settings::for_program_in(folder().as_deref(), program, known);
+    }
+
+    // The same with the files in a given config folder
+    pub fn for_program_in(folder: Option<&Path>, program: &[u8], known: Option<Settings>) -> Result<(Settings, Option<PathBuf>), String> {
+        let global = match folder {
+            Some(folder) => Settings::load(&folder.join("settings.toml"))?,
+            None => Settings::default(),
+        };
+        let path = folder.map(|folder| rom_file_in(folder, program));
+        let own = match &path {
+            Some(path) => Settings::load(path)?,
+            None => Settings::default(),
+        };
+        let base = match known {
+            Some(known) => known.over(global),
+            None => global,
+        };
+        return Ok((own.over(base), path));
+    }
+
+    // These settings where they are set and the base ones everywhere else
+    pub fn over(self: Self, base: Settings) -> Settings {
+        let mut keys = base.keys;
+        keys.extend(self.keys);
+        let mut pad = base.pad;
+        pad.extend(self.pad);
+        // Quirks for one platform mean little on another one
+        let quirks = match (&self.platform, self.quirks, base.quirks) {
+            (None, Some(own), Some(under)) => Some(format!("{},{}", under, own)),
+            (None, own, under) => own.or(under),
+            (Some(_), own, _) => own,
+        };
+        return Settings {
+            rom: self.rom.or(base.rom),
+            platform: self.platform.or(base.platform),
+            quirks,
+            ipf: self.ipf.or(base.ipf),
+            scale: self.scale.or(base.scale),
+            scaling: self.scaling.or(base.scaling),
+            palette: self.palette.or(base.palette),
+            phosphor: self.phosphor.or(base.phosphor),
+            filter: self.filter.or(base.filter),
+            upscaler: self.upscaler.or(base.upscaler),
+            volume: self.volume.or(base.volume),
+            keymap: self.keymap.or(base.keymap),
+            keys,
+            pad,
+        };
+    }
+
+    // Catch mistakes when the file is read rather than when the setting is used
+    fn check(self: &Self) -> Result<(), String> {
+        let mut quirks = Quirks::platform(self.platform.as_deref().unwrap_or("default"))?;
+        if let Some(list) = &self.quirks {
+            quirks.apply(list)?;
+        }
+        if let Some(palette) = &self.palette {
+            Palette::parse(palette)?;
+        }
+        if let Some(filter) = &self.filter {
+            Filter::parse(filter)?;
+        }
+        if let Some(upscaler) = &self.upscaler {
+            Upscaler::parse(upscaler)?;
+        }
+        if let Some(phosphor) = self.phosphor {
+            if !(0.0..=1.0).contains(&phosphor) {
+                return Err(String::from("phosphor must be a number from 0 to 1"));
+            }
+        }
+        return Ok(());
+    }
+}
+
+pub fn folder() -> Option<PathBuf> {
+    return dirs::config_dir().map(|config| config.join("chip8"));
+}
+
+pub fn global_file() -> Option<PathBuf> {
+    return folder().map(|folder| folder.join("settings.toml"));
+}
+
+pub fn rom_file(program: &[u8]) -> Option<PathBuf> {
+    return folder().map(|folder| rom_file_in(&folder, program));
+}
+
+fn rom_file_in(folder: &Path, program: &[u8]) -> PathBuf {
+    return folder.join("roms").join(format!("{}.toml", sha1(program)));
+}
+
+// Lowercase hex, the way the CHIP-8 database and sha1sum write it
+pub fn sha1(program: &[u8]) -> String {
+    return sha1_smol::Sha1::from(program).digest().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn own_settings_go_over_the_base_ones() {
+        let mut base = Settings { ipf: Some(15), scale: Some(3), quirks: Some(String::from("jump")), ..Settings::default() };
+        base.keys.insert(String::from("5"), vec![String::from("Space")]);
+        base.keys.insert(String::from("6"), vec![String::from("X")]);
+        let mut own = Settings { ipf: Some(30), quirks: Some(String::from("-clip")), ..Settings::default() };
+        own.keys.insert(String::from("5"), vec![String::from("Return")]);
+
+        let settings = own.clone().over(base.clone());
+        assert_eq!(settings.ipf, Some(30));
+        assert_eq!(settings.scale, Some(3));
+        assert_eq!(settings.quirks.as_deref(), Some("jump,-clip"));
+        assert_eq!(settings.keys["5"], vec![String::from("Return")]);
+        assert_eq!(settings.keys["6"], vec![String::from("X")]);
+
+        // Picking a platform starts the quirks over
+        own.platform = Some(String::from("schip"));
+        assert_eq!(own.clone().over(base.clone()).quirks.as_deref(), Some("-clip"));
+        own.quirks = None;
+        assert_eq!(own.over(base).quirks, None);
+    }
+
+    #[test]
+    fn program_settings_are_found_by_hash() {
+        let folder = std::env::temp_dir().join(format!("chip8-test-{}-settings", process::id()));
+        fs::create_dir_all(folder.join("roms")).unwrap();
+        fs::write(folder.join("settings.toml"), "ipf = 15\nscale = 3\n").unwrap();
+        let program = [0x00, 0xE0, 0x12, 0x02];
+        let path = folder.join("roms").join(format!("{}.toml", sha1(&program)));
+        fs::write(&path, "ipf = 30\n").unwrap();
+
+        let own = Settings::for_program_in(Some(&folder), &program, None);
+        let other = Settings::for_program_in(Some(&folder), &[0x12, 0x00], None);
+        fs::write(&path, "ipf = \"fast\"\n").unwrap();
+        let broken = Settings::for_program_in(Some(&folder), &program, None);
+        fs::remove_dir_all(&folder).unwrap();
+
+        let (settings, file) = own.unwrap();
+        assert_eq!(file, Some(path));
+        assert_eq!((settings.ipf, settings.scale), (Some(30), Some(3)));
+        assert_eq!(other.unwrap().0.ipf, Some(15));
+        assert!(broken.is_err());
+        assert!(Settings::for_program_in(None, &program, None).unwrap().1.is_none());
+    }
+}
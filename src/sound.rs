@@ -1,74 +1,136 @@
 /*
-    Most of this is copied from the Rust Bindings for SDL documentation
-    Tried to play around with the values to make sound more consistent
-    Needs Improvement
+    Plays the beeper through SDL. The device runs the whole time and the callback
+    follows the beeper state shared with the run loop sample by sample, instead of
+    pausing and resuming the device, which used to click and miss short beeps.
+    When capturing, the callback copies every buffer into a ring that the run loop
+    empties into a WAV file, as files should not be written from the audio thread
 */
 
+use chip8::beeper::{BeeperSettings, BeeperState, SampleRing, Synth, Tone, RECORDING_SAMPLE_RATE};
+use chip8::frontend::AudioSink;
+use chip8::wav::WavWriter;
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::audio::AudioDevice;
-use sdl2::AudioSubsystem;
 use sdl2::Sdl;
+use std::sync::Arc;
 
-pub struct SquareWave {
-    pub phase_inc: f32,
-    pub phase: f32,
-    pub volume: f32,
+// How much the capture ring holds, the run loop empties it every frame
+const CAPTURE_SECONDS: usize = 1;
+
+pub struct Beeper {
+    synth: Synth,
+    state: Arc<BeeperState>,
+    capture: Option<Arc<SampleRing>>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Beeper {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase >= 0.5 {
-                self.volume / 100.0
-            } else {
-                -self.volume / 100.0
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        self.synth.follow(&self.state, out);
+        if let Some(capture) = &self.capture {
+            capture.push(out);
         }
     }
 }
 
+// Why there is no sound. Without a device the program can play on silently, but
+// a capture file that was asked for and can not be written is a mistake to report
+pub enum SoundError {
+    Device(String),
+    Capture(String),
+}
+
 pub struct SoundSystem {
-    pub audio_subsystem: AudioSubsystem,
-    pub desired_spec: AudioSpecDesired,
-    pub device: AudioDevice<SquareWave>,
+    device: AudioDevice<Beeper>,
+    state: Arc<BeeperState>,
+    capture: Option<Capture>,
+}
+
+struct Capture {
+    ring: Arc<SampleRing>,
+    writer: WavWriter,
+    samples: Vec<f32>,      // Reused for every write
 }
 
 impl SoundSystem{
-    pub fn initialize(sdl_context: &Sdl) -> SoundSystem{
-        
-        let audio_subsystem = sdl_context.audio().unwrap();
+    // Open the named device or the default one, and the capture file if there is one
+    pub fn initialize(sdl_context: &Sdl, settings: &BeeperSettings, device_name: Option<&str>, capture_path: Option<&str>) -> Result<SoundSystem, SoundError> {
+
+        let audio_subsystem = sdl_context.audio().map_err(SoundError::Device)?;
+        if let Some(name) = device_name {
+            let count = audio_subsystem.num_audio_playback_devices().unwrap_or(0);
+            let names: Vec<String> = (0..count).filter_map(|i| audio_subsystem.audio_playback_device_name(i).ok()).collect();
+            if !names.iter().any(|device| device == name) {
+                return Err(SoundError::Device(format!("There is no audio device named '{}', the devices are: {}", name, names.join(", "))));
+            }
+        }
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(66000),  //44100
+            freq: Some(RECORDING_SAMPLE_RATE as i32),
             channels: Some(1),  // mono
-            samples: Some(512)       
+            samples: Some(512)  // About 12ms, short enough that beeps start on time
         };
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            // initialize the audio callback
-            SquareWave {
-                phase_inc: 330.0 / spec.freq as f32,  //441
-                phase: 0.0,
-                volume: 0.25,//0.0015,
+        let state = Arc::new(BeeperState::default());
+        let mut ring = None;
+        let device = audio_subsystem.open_playback(device_name, &desired_spec, |spec| {
+            // The device may not give us the rate we asked for
+            ring = capture_path.map(|_| Arc::new(SampleRing::new(spec.freq as usize * CAPTURE_SECONDS)));
+            Beeper {
+                synth: Synth::new(settings.clone(), spec.freq as u32),
+                state: Arc::clone(&state),
+                capture: ring.clone(),
             }
-        }).unwrap();
+        }).map_err(SoundError::Device)?;
+        let capture = match (capture_path, ring) {
+            (Some(path), Some(ring)) => Some(Capture {
+                ring,
+                writer: WavWriter::create(path, device.spec().freq as u32).map_err(SoundError::Capture)?,
+                samples: Vec::with_capacity(device.spec().freq as usize * CAPTURE_SECONDS),
+            }),
+            _ => None,
+        };
+        device.resume();
 
-        return SoundSystem {
-            audio_subsystem,
-            desired_spec,
+        return Ok(SoundSystem {
             device,
-        }
+            state,
+            capture,
+        });
     }
 
-    pub fn handle_timer(self: &mut Self, sound_timer: &u8) {
-        if *sound_timer > 0 {
-            self.device.resume();
-        }
-        else {
-            self.device.pause();
+    // Write out what the callback played since last time
+    fn write_capture(self: &mut Self) {
+        if let Some(capture) = &mut self.capture {
+            capture.ring.pop(&mut capture.samples);
+            let result = capture.writer.write(&capture.samples);
+            capture.samples.clear();
+            if let Err(e) = result {
+                eprintln!("Audio capture failed: {}", e);
+                self.capture = None;
+            }
         }
     }
-}
\ No newline at end of file
+
+    // Stop the device and finish the capture file, if there is one
+    pub fn finish(mut self: Self) -> Result<(), String> {
+        self.device.pause();
+        self.write_capture();
+        let capture = match self.capture {
+            Some(capture) => capture,
+            None => return Ok(()),
+        };
+        capture.writer.finish()?;
+        return match capture.ring.dropped() {
+            0 => Ok(()),
+            dropped => Err(format!("{} samples are missing, the capture fell behind the device", dropped)),
+        };
+    }
+}
+
+impl AudioSink for SoundSystem {
+    fn set_tone(&mut self, tone: &Tone) {
+        self.state.set(tone);
+        self.write_capture();
+    }
+}
@@ -0,0 +1,72 @@
+/*
+    Pause, frame advance and fast forward. The run loop asks how many emulated
+    frames to run on each pass, so all of these work in whole frames
+*/
+pub struct SpeedControl {
+    pub paused: bool,
+    pub fast_forward: bool,         // True while the fast forward key is held
+    pub fast_forward_rate: u32,     // Frames to run per frame when fast forwarding, 0 is unlimited
+    step_requested: bool,
+}
+
+impl SpeedControl {
+    pub fn new(fast_forward_rate: u32) -> SpeedControl {
+        return SpeedControl {
+            paused: false,
+            fast_forward: false,
+            fast_forward_rate,
+            step_requested: false,
+        };
+    }
+
+    pub fn toggle_pause(self: &mut Self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+    }
+
+    // Only does anything while paused, runs exactly one frame on the next pass
+    pub fn step(self: &mut Self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    pub fn frames_to_run(self: &mut Self) -> u32 {
+        if self.paused {
+            let frames = u32::from(self.step_requested);
+            self.step_requested = false;
+            return frames;
+        }
+        if self.fast_forward && self.fast_forward_rate > 0 {
+            return self.fast_forward_rate;
+        }
+        return 1;
+    }
+
+    // When fast forwarding without a limit we skip waiting for the frame deadline
+    pub fn unlimited(self: &Self) -> bool {
+        return !self.paused && self.fast_forward && self.fast_forward_rate == 0;
+    }
+
+    // Paused or fast forwarding, empty when running normally
+    pub fn state(self: &Self) -> String {
+        if self.paused {
+            return String::from("Paused");
+        }
+        if self.unlimited() {
+            return String::from("Fast Forward");
+        }
+        if self.fast_forward {
+            return format!("Fast Forward x{}", self.fast_forward_rate);
+        }
+        return String::new();
+    }
+
+    pub fn describe(self: &Self, instructions_per_frame: u32) -> String {
+        let state = self.state();
+        if state.is_empty() {
+            return format!("{} IPF", instructions_per_frame);
+        }
+        return format!("{} IPF [{}]", instructions_per_frame, state);
+    }
+}
@@ -0,0 +1,324 @@
+use super::options::{Charset, Options};
+use chip8::framebuffer::FrameBuffer;
+use chip8::beeper::Tone;
+use chip8::frontend::{AudioSink, InputEvent, InputSource, VideoSink};
+use chip8::keymap::Keymap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the rest of an escape sequence gets to arrive before Esc counts as the Esc key
+const ESCAPE_WAIT: Duration = Duration::from_millis(50);
+
+/*
+    Plays the program in the terminal, for SSH sessions and machines without a
+    display. The image is drawn with unicode half blocks (two pixels per character)
+    or braille (eight pixels per character) in 24 bit ANSI colors, and the keyboard
+    is read from stdin in raw mode. Terminals only send a character when a key is
+    pressed (and again when it repeats), never when it is released, so a key counts
+    as held until key_hold has passed without it being sent again. Only the
+    keymap's letters and digits work, terminals do not say where a key is
+*/
+pub fn open(options: &Options) -> Result<(TerminalVideo, TerminalAudio, TerminalInput), String> {
+    let terminal = RawTerminal::enter()?;
+    let video = TerminalVideo {
+        charset: options.charset,
+        background: options.palette.color(0),
+        title: String::new(),
+        out: String::new(),
+    };
+    let input = TerminalInput {
+        _terminal: terminal,
+        input: spawn_input_thread(),
+        keymap: options.keymap.clone(),
+        key_hold: Duration::from_millis(options.key_hold),
+        released_at: [None; 16],
+        pending: Vec::new(),
+        pending_since: None,
+    };
+    return Ok((video, TerminalAudio { beeping: false }, input));
+}
+
+pub struct TerminalVideo {
+    charset: Charset,
+    background: [u8; 3],        // Braille dots are only drawn for pixels that are not the background
+    title: String,
+    out: String,
+}
+
+impl VideoSink for TerminalVideo {
+    fn present(&mut self, frame: &FrameBuffer) {
+        self.out.clear();
+        self.out.push_str("\x1b[H");                                     // Back to the top left
+        match self.charset {
+            Charset::HalfBlock => draw_half_blocks(frame, &mut self.out),
+            Charset::Braille => draw_braille(frame, self.background, &mut self.out),
+        }
+        self.out.push_str(&format!("\x1b[0m\r\n{}  (Esc to quit, P to pause)\x1b[K", self.title));
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(self.out.as_bytes()).and_then(|_| stdout.flush());
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    // There is no room for the on-screen display when every pixel is a character or less
+    fn overlay(&self) -> bool {
+        return false;
+    }
+}
+
+// Rings the terminal bell when a beep starts
+pub struct TerminalAudio {
+    beeping: bool,
+}
+
+impl AudioSink for TerminalAudio {
+    fn set_tone(&mut self, tone: &Tone) {
+        let beeping = tone.beeping;
+        if beeping && !self.beeping {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+        }
+        self.beeping = beeping;
+    }
+}
+
+// Also keeps the terminal in raw mode until it is dropped
+pub struct TerminalInput {
+    _terminal: RawTerminal,
+    input: Receiver<u8>,
+    keymap: Keymap,
+    key_hold: Duration,
+    released_at: [Option<Instant>; 16],
+    pending: Vec<u8>,               // The start of an escape sequence whose end has not arrived yet
+    pending_since: Option<Instant>,
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, events: &mut Vec<InputEvent>) {
+        let now = Instant::now();
+        let mut bytes = mem::take(&mut self.pending);
+        let pending_since = self.pending_since.take();      // When what was left over from last time started
+        bytes.extend(self.input.try_iter());
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                0x03 => events.push(InputEvent::Quit),                      // Ctrl + C
+                // Arrows, function keys and the like are skipped whole
+                0x1B => match escape(&bytes[i..]) {
+                    Escape::Key => events.push(InputEvent::Quit),
+                    Escape::Sequence(length) => i += length - 1,
+                    Escape::Unfinished => {
+                        let since = if i == 0 { pending_since.unwrap_or(now) } else { now };
+                        if now < since + ESCAPE_WAIT {
+                            self.pending = bytes[i..].to_vec();
+                            self.pending_since = Some(since);
+                            break;
+                        }
+                        // Nothing more came, so it was the Esc key (or a sequence cut short)
+                        if bytes.len() - i == 1 {
+                            events.push(InputEvent::Quit);
+                        }
+                        i = bytes.len();
+                    },
+                },
+                byte => match typed(&self.keymap, byte) {
+                    Some(InputEvent::KeyDown(key)) => {
+                        if self.released_at[usize::from(key)].is_none() {
+                            events.push(InputEvent::KeyDown(key));
+                        }
+                        self.released_at[usize::from(key)] = Some(now + self.key_hold);
+                    },
+                    Some(event) => events.push(event),
+                    None => {},
+                },
+            }
+            i += 1;
+        }
+        for key in 0..16 {
+            if let Some(time) = self.released_at[key] {
+                if time <= now {
+                    events.push(InputEvent::KeyUp(key as u8));
+                    self.released_at[key] = None;
+                }
+            }
+        }
+    }
+}
+
+// What typing a letter or digit does. The keymap comes first, so P and N only
+// pause and step when no keypad key is bound to them
+fn typed(keymap: &Keymap, byte: u8) -> Option<InputEvent> {
+    if let Some(key) = keymap.key(&char::from(byte).to_string()) {
+        return Some(InputEvent::KeyDown(key));
+    }
+    return match byte {
+        b'p' | b'P' => Some(InputEvent::TogglePause),
+        b'n' | b'N' => Some(InputEvent::Step),
+        _ => None,
+    };
+}
+
+#[derive(Debug, PartialEq)]
+enum Escape {
+    Key,                // The Esc key, another Esc came straight after it
+    Sequence(usize),    // A key sent as an escape sequence, this many bytes long
+    Unfinished,         // The rest may still be on its way
+}
+
+// What the bytes starting with an Esc are. Arrows and function keys are CSI
+// sequences, Esc [ then parameters up to a final byte from @ to ~ (e.g. Esc [ 1 ; 5 A
+// for Ctrl + Up or Esc [ 1 5 ~ for F5), or SS3 ones, Esc O and one more byte (F1 to
+// F4). Esc and any other byte is that key with Alt held
+fn escape(bytes: &[u8]) -> Escape {
+    return match bytes.get(1) {
+        None => Escape::Unfinished,
+        Some(0x1B) => Escape::Key,
+        Some(b'[') => match bytes.iter().skip(2).position(|byte| (0x40..=0x7E).contains(byte)) {
+            Some(end) => Escape::Sequence(end + 3),
+            None => Escape::Unfinished,
+        },
+        Some(b'O') if bytes.len() < 3 => Escape::Unfinished,
+        Some(b'O') => Escape::Sequence(3),
+        Some(_) => Escape::Sequence(2),
+    };
+}
+
+// Every character is two pixels on top of each other, the top one in the foreground color
+fn draw_half_blocks(frame: &FrameBuffer, out: &mut String) {
+    let mut last = None;
+    for y in (0..frame.height).step_by(2) {
+        for x in 0..frame.width {
+            let top = frame.pixel(x, y);
+            let bottom = if y + 1 < frame.height { frame.pixel(x, y + 1) } else { top };
+            let colors = (top, bottom);
+            if last != Some(colors) {               // Only send colors when they change
+                out.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]));
+                last = Some(colors);
+            }
+            out.push('\u{2580}');
+        }
+        out.push_str("\x1b[0m\r\n");
+        last = None;
+    }
+}
+
+// Every character is a 2x4 block of pixels, a dot for each pixel that is not the background.
+// A character can only have one color so the first lit pixel's color is used
+fn draw_braille(frame: &FrameBuffer, background: [u8; 3], out: &mut String) {
+    // Bit for each dot, by row then column, from the unicode braille pattern layout
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    out.push_str(&format!("\x1b[48;2;{};{};{}m", background[0], background[1], background[2]));
+    for y in (0..frame.height).step_by(4) {
+        for x in (0..frame.width).step_by(2) {
+            let mut bits = 0;
+            let mut color = background;
+            for (dy, row) in DOTS.iter().enumerate() {
+                for (dx, bit) in row.iter().enumerate() {
+                    if x + dx >= frame.width || y + dy >= frame.height {
+                        continue;
+                    }
+                    let pixel = frame.pixel(x + dx, y + dy);
+                    if pixel[..3] != background {
+                        if bits == 0 {
+                            color = [pixel[0], pixel[1], pixel[2]];
+                        }
+                        bits |= bit;
+                    }
+                }
+            }
+            out.push_str(&format!("\x1b[38;2;{};{};{}m", color[0], color[1], color[2]));
+            out.push(char::from_u32(0x2800 + bits).unwrap());
+        }
+        out.push_str("\r\n");
+    }
+}
+
+fn spawn_input_thread() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 64];
+        let mut stdin = io::stdin();
+        while let Ok(count) = stdin.read(&mut buffer) {
+            if count == 0 {
+                break;
+            }
+            for byte in &buffer[..count] {
+                if sender.send(*byte).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    return receiver;
+}
+
+// Puts the terminal in raw mode on the alternate screen, and puts it back when dropped
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<RawTerminal, String> {
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(String::from("stdin is not a terminal"));
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            print!("\x1b[?1049h\x1b[?25l\x1b[2J");              // Alternate screen, hide the cursor, clear
+            return Ok(RawTerminal { original });
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequences_are_skipped_whole() {
+        assert_eq!(escape(b"\x1b[A"), Escape::Sequence(3));                // Up
+        assert_eq!(escape(b"\x1b[1;5Aw"), Escape::Sequence(6));            // Ctrl + Up
+        assert_eq!(escape(b"\x1b[15~1"), Escape::Sequence(5));             // F5
+        assert_eq!(escape(b"\x1bOP"), Escape::Sequence(3));                // F1
+        assert_eq!(escape(b"\x1bw"), Escape::Sequence(2));                 // Alt + W
+    }
+
+    #[test]
+    fn escape_waits_for_the_rest() {
+        assert_eq!(escape(b"\x1b"), Escape::Unfinished);
+        assert_eq!(escape(b"\x1b[1;"), Escape::Unfinished);
+        assert_eq!(escape(b"\x1bO"), Escape::Unfinished);
+        assert_eq!(escape(b"\x1b\x1b[A"), Escape::Key);
+    }
+
+    #[test]
+    fn bound_keys_win_over_pause_and_step() {
+        let keymap = Keymap::default();
+        assert_eq!(typed(&keymap, b'p'), Some(InputEvent::TogglePause));
+        assert_eq!(typed(&keymap, b'N'), Some(InputEvent::Step));
+        assert_eq!(typed(&keymap, b'1'), Some(InputEvent::KeyDown(0x0)));
+        let keymap = Keymap::from_toml("[keys]\n5 = [\"P\"]\n6 = [\"N\"]\n", "").unwrap();
+        assert_eq!(typed(&keymap, b'p'), Some(InputEvent::KeyDown(0x5)));
+        assert_eq!(typed(&keymap, b'n'), Some(InputEvent::KeyDown(0x6)));
+        assert_eq!(typed(&keymap, b'-'), None);
+    }
+}
@@ -0,0 +1,35 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+
+// If we fall further behind than this we give up on the missed frames instead
+// of running them all back to back (e.g. after the window was dragged)
+const MAX_CATCH_UP_FRAMES: u32 = 5;
+
+pub struct FrameTimer {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FrameTimer {
+    pub fn new(frame_rate: u32) -> FrameTimer {
+        return FrameTimer {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            next_frame: Instant::now(),
+        };
+    }
+
+    // Sleep until the deadline of the next frame. When we are running late we
+    // return straight away so the following frames can catch up
+    pub fn wait(self: &mut Self) {
+        self.next_frame += self.frame_duration;
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        }
+        else if now - self.next_frame > self.frame_duration * MAX_CATCH_UP_FRAMES {
+            self.next_frame = now;
+        }
+    }
+}
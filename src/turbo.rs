@@ -0,0 +1,141 @@
+/*
+    Turbo buttons and macros, between the input events and the Cpu's keyboard.
+    The keys the player holds are kept here and the keyboard is worked out from
+    them at the start of every emulated frame, with turbo keys flipping every
+    few frames while their button is held and macros holding keys for so many
+    frames per step. Everything counts emulated frames rather than time, so it
+    comes out the same at any speed and the movie (which records the keyboard
+    the Cpu got) plays it back exactly
+*/
+
+// Holding the button presses the key for this many frames, lets go for as many, and so on
+#[derive(Clone, Debug)]
+pub struct Turbo {
+    pub key: u8,
+    pub frames: u32,
+}
+
+// Keys to hold, as keyboard bits, and for how many frames, one after another
+#[derive(Clone, Debug, Default)]
+pub struct Macro {
+    pub steps: Vec<(u16, u32)>,
+}
+
+impl Macro {
+    fn length(self: &Self) -> u64 {
+        return self.steps.iter().map(|(_, frames)| u64::from(*frames)).sum();
+    }
+
+    // The keys held this many frames in, None once it has finished
+    fn keyboard(self: &Self, elapsed: u64) -> Option<u16> {
+        let mut end = 0;
+        for (keyboard, frames) in &self.steps {
+            end += u64::from(*frames);
+            if elapsed < end {
+                return Some(*keyboard);
+            }
+        }
+        return None;
+    }
+}
+
+#[derive(Default)]
+pub struct InputLayer {
+    pub turbos: Vec<Turbo>,
+    pub macros: Vec<Macro>,
+    holders: [u32; 16],                 // How many sources (the keyboard, pad, fingers on the panel...) hold each key
+    firing: Vec<(usize, u64)>,          // Turbo buttons held and the frame they were pressed on
+    playing: Vec<(usize, u64)>,         // Macros playing and the frame they started on
+}
+
+impl InputLayer {
+    pub fn new(turbos: Vec<Turbo>, macros: Vec<Macro>) -> InputLayer {
+        return InputLayer { turbos, macros, ..InputLayer::default() };
+    }
+
+    // Every source sends its own press and release, so a key stays down until
+    // the last source holding it lets go
+    pub fn press(self: &mut Self, key: u8) {
+        self.holders[usize::from(key & 0xF)] += 1;
+    }
+
+    pub fn release(self: &mut Self, key: u8) {
+        let holders = &mut self.holders[usize::from(key & 0xF)];
+        *holders = holders.saturating_sub(1);
+    }
+
+    fn held(self: &Self) -> u16 {
+        let mut keyboard = 0;
+        for (key, holders) in self.holders.iter().enumerate() {
+            if *holders > 0 {
+                keyboard |= 1 << key;
+            }
+        }
+        return keyboard;
+    }
+
+    // A turbo button went down or up, frame is the next frame to run
+    pub fn turbo(self: &mut Self, turbo: usize, held: bool, frame: u64) {
+        self.firing.retain(|(firing, _)| *firing != turbo);
+        if held && turbo < self.turbos.len() {
+            self.firing.push((turbo, frame));
+        }
+    }
+
+    // Start a macro from the next frame, pressing it again while it plays starts it over
+    pub fn play(self: &mut Self, number: usize, frame: u64) {
+        self.playing.retain(|(playing, _)| *playing != number);
+        if number < self.macros.len() {
+            self.playing.push((number, frame));
+        }
+    }
+
+    // The keyboard for a frame, call with the frames in order as finished macros are dropped
+    pub fn keyboard(self: &mut Self, frame: u64) -> u16 {
+        let mut keyboard = self.held();
+        for (turbo, start) in &self.firing {
+            let turbo = &self.turbos[*turbo];
+            let frames = u64::from(turbo.frames.max(1));
+            if frame.saturating_sub(*start) / frames % 2 == 0 {
+                keyboard |= 1 << turbo.key;
+            }
+        }
+        let macros = &self.macros;
+        self.playing.retain(|(number, start)| frame.saturating_sub(*start) < macros[*number].length());
+        for (number, start) in &self.playing {
+            keyboard |= self.macros[*number].keyboard(frame.saturating_sub(*start)).unwrap_or(0);
+        }
+        return keyboard;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_stay_down_while_any_source_holds_them() {
+        let mut input = InputLayer::default();
+        input.press(5);                 // The keyboard
+        input.press(5);                 // A finger on the panel
+        input.press(2);
+        assert_eq!(input.keyboard(0), 0x0024);
+        input.release(5);
+        assert_eq!(input.keyboard(1), 0x0024);
+        input.release(5);
+        input.release(5);               // One release too many changes nothing
+        assert_eq!(input.keyboard(2), 0x0004);
+        input.press(5);
+        assert_eq!(input.keyboard(3), 0x0024);
+    }
+
+    #[test]
+    fn turbo_and_macros_add_to_the_held_keys() {
+        let mut input = InputLayer::new(vec![Turbo { key: 1, frames: 2 }], vec![Macro { steps: vec![(0x0100, 1), (0x0200, 2)] }]);
+        input.press(0);
+        input.turbo(0, true, 0);
+        input.play(0, 0);
+        let keyboards: Vec<u16> = (0..5).map(|frame| input.keyboard(frame)).collect();
+        assert_eq!(keyboards, [0x0103, 0x0203, 0x0201, 0x0001, 0x0003]);
+    }
+}
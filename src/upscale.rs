@@ -0,0 +1,282 @@
+pub const UPSCALERS: [&str; 6] = ["none", "epx", "scale2x", "scale3x", "scale4x", "xbr"];
+
+/*
+    Pixel art upscalers that work on the logical display values before the palette,
+    so the result is still made of display values and any palette can be used with it.
+    They round off the corners of diagonal edges instead of leaving staircases
+*/
+#[derive(Clone, Copy, PartialEq)]
+pub enum Upscaler {
+    None,
+    Epx,            // Eric's Pixel Expansion, the original 2x algorithm
+    Scale2x,        // EPX with extra checks so thin lines and single pixels are kept
+    Scale3x,
+    Scale4x,        // Scale2x done twice
+    Xbr,            // xBR-like 4x, cuts the corners of edges along the diagonal instead of filling whole quarters
+}
+
+impl Upscaler {
+    pub fn parse(value: &str) -> Result<Upscaler, String> {
+        return match value {
+            "none" => Ok(Upscaler::None),
+            "epx" => Ok(Upscaler::Epx),
+            "scale2x" => Ok(Upscaler::Scale2x),
+            "scale3x" => Ok(Upscaler::Scale3x),
+            "scale4x" => Ok(Upscaler::Scale4x),
+            "xbr" => Ok(Upscaler::Xbr),
+            _ => Err(format!("Upscaler '{}' must be one of {}", value, UPSCALERS.join(", "))),
+        };
+    }
+
+    pub fn name(self: &Self) -> &'static str {
+        return match self {
+            Upscaler::None => "none",
+            Upscaler::Epx => "epx",
+            Upscaler::Scale2x => "scale2x",
+            Upscaler::Scale3x => "scale3x",
+            Upscaler::Scale4x => "scale4x",
+            Upscaler::Xbr => "xbr",
+        };
+    }
+
+    // The next upscaler, for cycling through them with a hotkey
+    pub fn next(self: &Self) -> Upscaler {
+        let index = UPSCALERS.iter().position(|name| *name == self.name()).unwrap();
+        return Upscaler::parse(UPSCALERS[(index + 1) % UPSCALERS.len()]).unwrap();
+    }
+
+    pub fn factor(self: &Self) -> usize {
+        return match self {
+            Upscaler::None => 1,
+            Upscaler::Epx | Upscaler::Scale2x => 2,
+            Upscaler::Scale3x => 3,
+            Upscaler::Scale4x | Upscaler::Xbr => 4,
+        };
+    }
+
+    // Upscale the display into out and return the new resolution
+    pub fn apply(self: &Self, display: &[u8], resolution: (usize, usize), out: &mut Vec<u8>) -> (usize, usize) {
+        let source = Image { pixels: display, width: resolution.0, height: resolution.1 };
+        match self {
+            Upscaler::None => {
+                out.clear();
+                out.extend_from_slice(display);
+            },
+            Upscaler::Epx => epx(&source, out),
+            Upscaler::Scale2x => scale2x(&source, out),
+            Upscaler::Scale3x => scale3x(&source, out),
+            Upscaler::Scale4x => {
+                let mut doubled = Vec::new();
+                scale2x(&source, &mut doubled);
+                let doubled = Image { pixels: &doubled, width: resolution.0 * 2, height: resolution.1 * 2 };
+                scale2x(&doubled, out);
+            },
+            Upscaler::Xbr => xbr(&source, out),
+        }
+        let factor = self.factor();
+        return (resolution.0 * factor, resolution.1 * factor);
+    }
+}
+
+struct Image<'a> {
+    pixels: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Image<'a> {
+    // Pixels past the edges repeat the edge
+    fn get(self: &Self, x: usize, y: usize, dx: isize, dy: isize) -> u8 {
+        let x = (x as isize + dx).max(0).min(self.width as isize - 1) as usize;
+        let y = (y as isize + dy).max(0).min(self.height as isize - 1) as usize;
+        return self.pixels[y * self.width + x];
+    }
+
+    // The 3x3 block around a pixel, named like the Scale2x docs
+    //  A B C
+    //  D E F
+    //  G H I
+    fn block(self: &Self, x: usize, y: usize) -> [u8; 9] {
+        return [
+            self.get(x, y, -1, -1), self.get(x, y, 0, -1), self.get(x, y, 1, -1),
+            self.get(x, y, -1, 0), self.get(x, y, 0, 0), self.get(x, y, 1, 0),
+            self.get(x, y, -1, 1), self.get(x, y, 0, 1), self.get(x, y, 1, 1),
+        ];
+    }
+}
+
+// Run a per pixel function producing factor x factor values and lay them out in out
+fn expand<F>(source: &Image, factor: usize, out: &mut Vec<u8>, mut cell: F)
+    where F: FnMut(usize, usize, &mut [u8]) {
+    let out_width = source.width * factor;
+    out.clear();
+    out.resize(out_width * source.height * factor, 0);
+    let mut values = vec![0; factor * factor];
+    for y in 0..source.height {
+        for x in 0..source.width {
+            cell(x, y, &mut values);
+            for sy in 0..factor {
+                let row = (y * factor + sy) * out_width + x * factor;
+                out[row..row + factor].copy_from_slice(&values[sy * factor..(sy + 1) * factor]);
+            }
+        }
+    }
+}
+
+fn epx(source: &Image, out: &mut Vec<u8>) {
+    expand(source, 2, out, |x, y, values| {
+        let [_, a, _, c, p, b, _, d, _] = source.block(x, y);
+        let mut cell = [p; 4];
+        if c == a { cell[0] = a; }
+        if a == b { cell[1] = b; }
+        if d == c { cell[2] = c; }
+        if b == d { cell[3] = d; }
+        // If three or more neighbours match the pixel is left alone
+        let neighbours = [a, b, c, d];
+        if neighbours.iter().any(|n| neighbours.iter().filter(|m| *m == n).count() >= 3) {
+            cell = [p; 4];
+        }
+        values.copy_from_slice(&cell);
+    });
+}
+
+fn scale2x(source: &Image, out: &mut Vec<u8>) {
+    expand(source, 2, out, |x, y, values| {
+        let [_, b, _, d, e, f, _, h, _] = source.block(x, y);
+        if b != h && d != f {
+            values[0] = if d == b { d } else { e };
+            values[1] = if b == f { f } else { e };
+            values[2] = if d == h { d } else { e };
+            values[3] = if h == f { f } else { e };
+        }
+        else {
+            values.fill(e);
+        }
+    });
+}
+
+fn scale3x(source: &Image, out: &mut Vec<u8>) {
+    expand(source, 3, out, |x, y, values| {
+        let [a, b, c, d, e, f, g, h, i] = source.block(x, y);
+        values.fill(e);
+        if b != h && d != f {
+            values[0] = if d == b { d } else { e };
+            values[1] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+            values[2] = if b == f { f } else { e };
+            values[3] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+            values[5] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+            values[6] = if d == h { d } else { e };
+            values[7] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+            values[8] = if h == f { f } else { e };
+        }
+    });
+}
+
+// Uses the same edge checks as Scale2x, but only the triangle of sub pixels on the
+// outside of the diagonal through each corner is filled, giving straight 45 degree edges
+fn xbr(source: &Image, out: &mut Vec<u8>) {
+    expand(source, 4, out, |x, y, values| {
+        let [_, b, _, d, e, f, _, h, _] = source.block(x, y);
+        values.fill(e);
+        if b == h || d == f {
+            return;
+        }
+        for sy in 0..4 {
+            for sx in 0..4 {
+                let (left, top) = (sx, sy);
+                let (right, bottom) = (3 - sx, 3 - sy);
+                let value = &mut values[sy * 4 + sx];
+                if d == b && left + top < 3 { *value = b; }
+                else if b == f && right + top < 3 { *value = f; }
+                else if d == h && left + bottom < 3 { *value = d; }
+                else if h == f && right + bottom < 3 { *value = h; }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A corner with its bottom right pixel off, so there is one diagonal edge to round off
+    const CORNER: [u8; 4] = [
+        1, 1,
+        1, 0,
+    ];
+
+    fn upscale(upscaler: Upscaler, display: &[u8], resolution: (usize, usize)) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (width, height) = upscaler.apply(display, resolution, &mut out);
+        assert_eq!(out.len(), width * height);
+        return out;
+    }
+
+    #[test]
+    fn epx_and_scale2x_fill_the_inside_corner() {
+        let expected = [
+            1, 1, 1, 1,
+            1, 1, 1, 1,
+            1, 1, 1, 0,
+            1, 1, 0, 0,
+        ];
+        assert_eq!(upscale(Upscaler::Epx, &CORNER, (2, 2)), expected);
+        assert_eq!(upscale(Upscaler::Scale2x, &CORNER, (2, 2)), expected);
+    }
+
+    #[test]
+    fn scale2x_keeps_single_pixels_and_thin_lines() {
+        let dot = [
+            0, 0, 0,
+            0, 1, 0,
+            0, 0, 0,
+        ];
+        let out = upscale(Upscaler::Scale2x, &dot, (3, 3));
+        assert_eq!(out.iter().filter(|value| **value == 1).count(), 4);
+        let line = [
+            1, 0, 0,
+            0, 1, 0,
+            0, 0, 1,
+        ];
+        // The steps of the diagonal line are filled in so it stays one connected line
+        assert_eq!(upscale(Upscaler::Scale2x, &line, (3, 3)), [
+            1, 1, 0, 0, 0, 0,
+            1, 0, 1, 0, 0, 0,
+            0, 1, 1, 1, 0, 0,
+            0, 0, 1, 1, 1, 0,
+            0, 0, 0, 1, 0, 1,
+            0, 0, 0, 0, 1, 1,
+        ]);
+    }
+
+    #[test]
+    fn scale3x_rounds_the_corner_in_thirds() {
+        assert_eq!(upscale(Upscaler::Scale3x, &CORNER, (2, 2)), [
+            1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 0,
+            1, 1, 1, 1, 0, 0,
+            1, 1, 1, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn scale4x_is_scale2x_twice() {
+        assert_eq!(upscale(Upscaler::Scale4x, &CORNER, (2, 2)), [
+            1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 0,
+            1, 1, 1, 1, 1, 0, 0, 0,
+            1, 1, 1, 1, 1, 0, 0, 0,
+            1, 1, 1, 1, 0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn none_copies_the_display() {
+        assert_eq!(upscale(Upscaler::None, &CORNER, (2, 2)), CORNER);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+/*
+    Writes mono 16 bit PCM WAV files a few samples at a time. The sizes in the
+    header are not known until the end, so they are filled in by finish
+*/
+pub struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<WavWriter, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+        let mut writer = WavWriter { file: BufWriter::new(file), samples: 0 };
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());              // File size - 8, filled in later
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());             // Size of the fmt chunk
+        header.extend_from_slice(&1u16.to_le_bytes());              // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());              // Mono
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Bytes per second
+        header.extend_from_slice(&2u16.to_le_bytes());              // Bytes per sample
+        header.extend_from_slice(&16u16.to_le_bytes());             // Bits per sample
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());              // Data size, filled in later
+        writer.file.write_all(&header).map_err(|e| e.to_string())?;
+        return Ok(writer);
+    }
+
+    pub fn write(self: &mut Self, samples: &[f32]) -> Result<(), String> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            self.file.write_all(&value.to_le_bytes()).map_err(|e| e.to_string())?;
+        }
+        self.samples += samples.len() as u32;
+        return Ok(());
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let data_size = self.samples * 2;
+        let result = (|| {
+            self.file.seek(SeekFrom::Start(4))?;
+            self.file.write_all(&(36 + data_size).to_le_bytes())?;
+            self.file.seek(SeekFrom::Start(40))?;
+            self.file.write_all(&data_size.to_le_bytes())?;
+            self.file.flush()
+        })();
+        return result.map_err(|e| e.to_string());
+    }
+}
+
+// Read a PCM WAV file (8 or 16 bit, any number of channels) as mono samples from -1 to 1,
+// with its sample rate
+pub fn read(path: &str) -> Result<(Vec<f32>, u32), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(format!("{} is not a WAV file", path));
+    }
+    let mut format = None;                  // Channels, sample rate and bits per sample
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let size = u32::from_le_bytes([bytes[position + 4], bytes[position + 5], bytes[position + 6], bytes[position + 7]]) as usize;
+        let chunk = &bytes[position + 8..(position + 8 + size).min(bytes.len())];
+        if id == b"fmt " && chunk.len() >= 16 {
+            let word = |i: usize| u16::from_le_bytes([chunk[i], chunk[i + 1]]);
+            if word(0) != 1 {
+                return Err(format!("{} is compressed, only PCM WAV files can be read", path));
+            }
+            let rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            format = Some((usize::from(word(2)).max(1), rate, word(14)));
+        }
+        if id == b"data" {
+            let (channels, rate, bits) = format.ok_or(format!("{} has no format before its data", path))?;
+            let samples: Vec<f32> = match bits {
+                8 => chunk.iter().map(|byte| (f32::from(*byte) - 128.0) / 128.0).collect(),
+                16 => chunk.chunks_exact(2).map(|pair| f32::from(i16::from_le_bytes([pair[0], pair[1]])) / 32768.0).collect(),
+                _ => return Err(format!("{} has {} bit samples, only 8 and 16 bit can be read", path, bits)),
+            };
+            let mono = samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect();
+            return Ok((mono, rate));
+        }
+        position += 8 + size + (size & 1);  // Chunks are padded to an even size
+    }
+    return Err(format!("{} has no samples", path));
+}